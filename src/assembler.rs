@@ -0,0 +1,766 @@
+use crate::container::{Container, SourceMap};
+use crate::fade::{Easing, EASING_NAMES};
+use crate::ledstrip_controller::{ProgramStripCommand, PROGRAM_NAMES, PROGRAM_SPEEDS};
+use crate::registers::{
+    get_register_code_by_name, get_register_size, REGISTER_NAMES, RGD, RGI, RGL,
+};
+use crate::runtime::{OverflowMode, VM_VERSION};
+use crate::tokens::{
+    AddToken, AndToken, ClearToken, CmdToken, CopyToken, DebugToken, DivToken, ExitToken,
+    FadeToken, FillToken, GotoToken, HslToken, HsvToken, JeToken, JgToken, JlToken, LabelToken,
+    LoadToken, LshToken, ModToken, MulToken, NotToken, NrtToken, OrToken, OverflowToken,
+    PauseToken, PixelToken, PowToken, PrintToken, RgbHslToken, RgbHsvToken, RotateToken, RshToken,
+    SendToken, SetToken, SetwToken, ShiftToken, ShowToken, StatusToken, SubToken, Token,
+    WriteToken, XorToken,
+};
+use std::collections::HashSet;
+use std::num::ParseIntError;
+
+const MNEMONICS: [&str; 41] = [
+    "exit", "set", "setw", "copy", "load", "clear", "write", "label", "goto", "debug", "print",
+    "add", "sub", "mul", "div", "mod", "lsh", "rsh", "and", "or", "not", "xor", "pow", "nrt", "jg",
+    "jl", "je", "pixel", "fill", "shift", "rotate", "show", "hsv", "hsl", "rgbhsv", "rgbhsl",
+    "pause", "cmd", "send", "status", "fade",
+];
+const DIRECTIVES: [&str; 2] = [".overflow", ".data"];
+const OVERFLOW_MODES: [&str; 3] = ["wrap", "saturate", "trap"];
+
+/// An error in the assembly with the position of the offending token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(word: &Word, message: String) -> Self {
+        Self {
+            line: word.line,
+            column: word.column,
+            length: word.text.chars().count(),
+            message,
+            hint: None,
+        }
+    }
+
+    fn with_hint(mut self, hint: Option<String>) -> Self {
+        self.hint = hint;
+        self
+    }
+
+    /// Formats the diagnostic with the source line and the offending token highlighted
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let source_line = source.lines().nth(self.line - 1).unwrap_or("");
+        let number = self.line.to_string();
+        let padding = " ".repeat(number.len());
+        let indent: String = source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let mut output = format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            padding,
+            file_name,
+            self.line,
+            self.column,
+            padding,
+            number,
+            source_line,
+            padding,
+            indent,
+            "^".repeat(self.length.max(1))
+        );
+        if let Some(hint) = &self.hint {
+            output.push_str(&format!("\n{} = hint: {}", padding, hint));
+        }
+
+        output
+    }
+}
+
+/// A whitespace separated part of a line
+struct Word<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+/// A line of the source without comments
+struct Line<'a> {
+    words: Vec<Word<'a>>,
+    number: usize,
+    end_column: usize,
+}
+
+impl<'a> Line<'a> {
+    fn parse(number: usize, line: &'a str) -> Self {
+        let code = line.split('#').next().unwrap_or("");
+        let mut words = Vec::new();
+        let mut start = None;
+
+        for (column, (index, c)) in code.char_indices().enumerate() {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some((index, column)),
+                (true, Some((start_index, start_column))) => {
+                    words.push(Word {
+                        text: &code[start_index..index],
+                        line: number,
+                        column: start_column + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some((start_index, start_column)) = start {
+            words.push(Word {
+                text: &code[start_index..],
+                line: number,
+                column: start_column + 1,
+            });
+        }
+
+        Self {
+            words,
+            number,
+            end_column: code.trim_end().chars().count() + 1,
+        }
+    }
+
+    /// Returns the operands of the instruction on this line
+    fn operands(&self) -> Operands<'_, 'a> {
+        Operands {
+            line: self,
+            next: 1,
+        }
+    }
+}
+
+/// Reads the operands of an instruction
+struct Operands<'l, 'a> {
+    line: &'l Line<'a>,
+    next: usize,
+}
+
+impl<'l, 'a> Operands<'l, 'a> {
+    /// Returns the next operand or an error describing the missing operand
+    fn next(&mut self, expected: &str) -> Result<&'l Word<'a>, Diagnostic> {
+        let word = self.line.words.get(self.next).ok_or_else(|| Diagnostic {
+            line: self.line.number,
+            column: self.line.end_column,
+            length: 1,
+            message: format!(
+                "missing operand for '{}': expected {}",
+                self.line.words[0].text, expected
+            ),
+            hint: None,
+        })?;
+        self.next += 1;
+
+        Ok(word)
+    }
+
+    /// Returns the next operand if there is one
+    fn optional(&mut self) -> Option<&'l Word<'a>> {
+        let word = self.line.words.get(self.next)?;
+        self.next += 1;
+
+        Some(word)
+    }
+
+    fn register(&mut self) -> Result<u8, Diagnostic> {
+        let word = self.next("a register")?;
+        get_register_code_by_name(word.text).ok_or_else(|| {
+            Diagnostic::new(word, format!("unknown register '{}'", word.text)).with_hint(suggest(
+                word.text,
+                REGISTER_NAMES.iter().map(|(name, _)| *name),
+            ))
+        })
+    }
+
+    /// Reads a register that holds 4 bytes
+    fn wide_register(&mut self) -> Result<u8, Diagnostic> {
+        let register = self.register()?;
+        if get_register_size(register) == Some(4) {
+            return Ok(register);
+        }
+        let word = &self.line.words[self.next - 1];
+
+        Err(Diagnostic::new(
+            word,
+            format!("the register '{}' only holds 1 byte", word.text),
+        )
+        .with_hint(Some(format!("use set to write to {}", word.text))))
+    }
+
+    /// Reads a value that has to fit into the given number of bytes
+    fn value(&mut self, size: usize) -> Result<u32, Diagnostic> {
+        parse_immediate(self.next("a value")?, size)
+    }
+
+    /// Fails if there are operands left
+    fn finish(self) -> Result<(), Diagnostic> {
+        if let Some(word) = self.line.words.get(self.next) {
+            Err(Diagnostic::new(
+                word,
+                format!(
+                    "unexpected operand '{}' for '{}'",
+                    word.text, self.line.words[0].text
+                ),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The ids of all named labels
+struct Labels {
+    named: Vec<(u32, String)>,
+}
+
+impl Labels {
+    /// Assigns ids to all named labels in the source. The ids are the lowest
+    /// ones that aren't used by numeric labels in the order of the definitions
+    fn allocate(lines: &[Line], diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let mut numeric = HashSet::new();
+        let mut names: Vec<&str> = Vec::new();
+
+        for line in lines {
+            if line.words[0].text != "label" {
+                continue;
+            }
+            if let Some(word) = line.words.get(1) {
+                if let Ok(id) = parse_value(word.text) {
+                    if !numeric.insert(id) {
+                        diagnostics.push(Diagnostic::new(
+                            word,
+                            format!("the label {} is defined multiple times", word.text),
+                        ));
+                    }
+                } else if let Some(name) = get_label_name(word.text) {
+                    if names.contains(&name) {
+                        diagnostics.push(Diagnostic::new(
+                            word,
+                            format!("the label '{}' is defined multiple times", name),
+                        ));
+                    } else {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+
+        let mut named = Vec::new();
+        let mut next_id = 0;
+        for name in names {
+            while numeric.contains(&next_id) {
+                next_id += 1;
+            }
+            named.push((next_id, name.to_string()));
+            next_id += 1;
+        }
+
+        Self { named }
+    }
+
+    /// Returns the id of a label that is either given as a number or a name
+    fn resolve(&self, word: &Word) -> Result<u32, Diagnostic> {
+        if let Ok(id) = parse_value(word.text) {
+            return Ok(id);
+        }
+        let name = get_label_name(word.text)
+            .ok_or_else(|| Diagnostic::new(word, format!("invalid label '{}'", word.text)))?;
+
+        self.named
+            .iter()
+            .find(|(_, label)| label == name)
+            .map(|(id, _)| *id)
+            .ok_or_else(|| {
+                Diagnostic::new(word, format!("unknown label '{}'", name)).with_hint(suggest(
+                    name,
+                    self.named.iter().map(|(_, label)| label.as_str()),
+                ))
+            })
+    }
+}
+
+/// Assembles the source into a bytecode container or plain bytecode for legacy.
+/// All errors are collected and returned sorted by their position
+pub fn assemble(source: &str, legacy: bool) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let (container, _) = assemble_container(source, legacy)?;
+
+    if legacy {
+        Ok(container.code)
+    } else {
+        Ok(container.to_bytes())
+    }
+}
+
+/// Assembles the source into a bytecode container with a source map
+/// that refers to the lines of the given file
+pub fn assemble_with_source_map(source: &str, file: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let (mut container, lines) = assemble_container(source, false)?;
+    container.source_map = Some(SourceMap {
+        file: file.to_string(),
+        lines,
+    });
+
+    Ok(container.to_bytes())
+}
+
+/// Assembles the source into a container.
+/// Returns the line of every instruction as well
+fn assemble_container(
+    source: &str,
+    legacy: bool,
+) -> Result<(Container, Vec<u32>), Vec<Diagnostic>> {
+    let lines: Vec<Line> = source
+        .lines()
+        .enumerate()
+        .map(|(index, line)| Line::parse(index + 1, line))
+        .filter(|line| !line.words.is_empty())
+        .collect();
+    let mut diagnostics = Vec::new();
+    let labels = Labels::allocate(&lines, &mut diagnostics);
+    let mut container = Container::new(Vec::new());
+    container.min_vm_version = VM_VERSION;
+    let mut instruction_lines = Vec::new();
+
+    for line in &lines {
+        let result = if line.words[0].text.starts_with('.') {
+            assemble_directive(line, legacy, &mut container)
+        } else {
+            get_tokens(line, &labels).map(|tokens| {
+                for token in tokens {
+                    container.code.append(&mut token.to_bytecode());
+                    instruction_lines.push(line.number as u32);
+                }
+            })
+        };
+        if let Err(diagnostic) = result {
+            diagnostics.push(diagnostic);
+        }
+    }
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        return Err(diagnostics);
+    }
+    container.symbols = labels.named;
+
+    Ok((container, instruction_lines))
+}
+
+/// Applies a directive to the container
+fn assemble_directive(
+    line: &Line,
+    legacy: bool,
+    container: &mut Container,
+) -> Result<(), Diagnostic> {
+    let directive = &line.words[0];
+    let mut operands = line.operands();
+
+    match directive.text {
+        ".overflow" => {
+            let word = operands.next("an overflow mode")?;
+            let mode: OverflowMode = word.text.parse().map_err(|_| {
+                Diagnostic::new(word, format!("unknown overflow mode '{}'", word.text))
+                    .with_hint(suggest(word.text, OVERFLOW_MODES.iter().copied()))
+            })?;
+            if container.overflow_mode.is_some() {
+                return Err(Diagnostic::new(
+                    directive,
+                    "the overflow mode is already set".to_string(),
+                ));
+            }
+            if legacy {
+                container
+                    .code
+                    .append(&mut OverflowToken { mode }.to_bytecode());
+            }
+            container.overflow_mode = Some(mode);
+        }
+        ".data" => {
+            let address = operands.value(4)?;
+            let value = operands.value(4)?;
+            if legacy {
+                return Err(Diagnostic::new(
+                    directive,
+                    "data directives are not supported in legacy bytecode".to_string(),
+                ));
+            }
+            container.data.push((address, value));
+        }
+        _ => {
+            return Err(Diagnostic::new(
+                directive,
+                format!("unknown directive '{}'", directive.text),
+            )
+            .with_hint(suggest(directive.text, DIRECTIVES.iter().copied())))
+        }
+    }
+
+    operands.finish()
+}
+
+/// Parses the line into tokens expanding jumps to labels
+fn get_tokens(line: &Line, labels: &Labels) -> Result<Vec<Box<dyn Token>>, Diagnostic> {
+    let instruction = &line.words[0];
+    let mut operands = line.operands();
+
+    let tokens: Vec<Box<dyn Token>> = match instruction.text {
+        "exit" => vec![Box::new(ExitToken {
+            register: operands.register()?,
+        })],
+        "set" => {
+            let value = operands.value(1)?;
+            vec![Box::new(SetToken {
+                value: value as u8,
+                register: operands.register()?,
+            })]
+        }
+        "setw" => vec![Box::new(SetwToken {
+            value: operands.value(4)?,
+            register: operands.wide_register()?,
+        })],
+        "copy" => vec![Box::new(CopyToken {
+            register_1: operands.register()?,
+            register_2: operands.register()?,
+        })],
+        "clear" => vec![Box::new(ClearToken {
+            register: operands.register()?,
+        })],
+        "print" => vec![Box::new(PrintToken {
+            register: operands.register()?,
+        })],
+        "label" => vec![Box::new(LabelToken {
+            value: labels.resolve(operands.next("a label")?)?,
+        })],
+        "cmd" => {
+            let mut tokens: Vec<Box<dyn Token>> = Vec::new();
+            if let Some(word) = operands.optional() {
+                let program = ProgramStripCommand::from_name(word.text).ok_or_else(|| {
+                    Diagnostic::new(word, format!("unknown program '{}'", word.text)).with_hint(
+                        suggest(word.text, PROGRAM_NAMES.iter().map(|(name, _)| *name)),
+                    )
+                })?;
+                tokens.push(Box::new(SetToken {
+                    value: program as u8,
+                    register: RGD,
+                }));
+                let word = operands.next("a speed")?;
+                let speed = parse_immediate(word, 1)? as u8;
+                if !PROGRAM_SPEEDS.contains(&speed) {
+                    return Err(Diagnostic::new(
+                        word,
+                        format!(
+                            "the speed {} is outside of 0x{:02X} to 0x{:02X}",
+                            word.text,
+                            PROGRAM_SPEEDS.start(),
+                            PROGRAM_SPEEDS.end()
+                        ),
+                    ));
+                }
+                tokens.push(Box::new(SetToken {
+                    value: speed,
+                    register: RGI,
+                }));
+            }
+            tokens.push(Box::new(CmdToken));
+            tokens
+        }
+        "fade" => {
+            let mut easing = Easing::Linear;
+            let mut background = false;
+            if let Some(word) = operands.optional() {
+                if word.text == "background" {
+                    background = true;
+                } else {
+                    easing = word.text.parse().map_err(|_| {
+                        Diagnostic::new(word, format!("unknown easing '{}'", word.text))
+                            .with_hint(suggest(word.text, EASING_NAMES.iter().copied()))
+                    })?;
+                    if let Some(word) = operands.optional() {
+                        if word.text != "background" {
+                            return Err(Diagnostic::new(
+                                word,
+                                format!("expected 'background' but got '{}'", word.text),
+                            ));
+                        }
+                        background = true;
+                    }
+                }
+            }
+            vec![Box::new(FadeToken { easing, background })]
+        }
+        "goto" | "jg" | "jl" | "je" => {
+            let mut tokens = Vec::new();
+            if let Some(target) = operands.optional() {
+                tokens.push(load_label(labels.resolve(target)?));
+            }
+            tokens.push(get_plain_token(instruction.text).unwrap());
+            tokens
+        }
+        name => vec![get_plain_token(name).ok_or_else(|| {
+            Diagnostic::new(instruction, format!("unknown instruction '{}'", name))
+                .with_hint(suggest(name, MNEMONICS.iter().copied()))
+        })?],
+    };
+    operands.finish()?;
+
+    Ok(tokens)
+}
+
+/// Returns the token for instructions without operands
+fn get_plain_token(name: &str) -> Option<Box<dyn Token>> {
+    let token: Box<dyn Token> = match name {
+        "load" => Box::new(LoadToken),
+        "write" => Box::new(WriteToken),
+        "goto" => Box::new(GotoToken),
+        "debug" => Box::new(DebugToken),
+        "add" => Box::new(AddToken),
+        "sub" => Box::new(SubToken),
+        "mul" => Box::new(MulToken),
+        "div" => Box::new(DivToken),
+        "mod" => Box::new(ModToken),
+        "lsh" => Box::new(LshToken),
+        "rsh" => Box::new(RshToken),
+        "and" => Box::new(AndToken),
+        "or" => Box::new(OrToken),
+        "not" => Box::new(NotToken),
+        "xor" => Box::new(XorToken),
+        "pow" => Box::new(PowToken),
+        "nrt" => Box::new(NrtToken),
+        "jg" => Box::new(JgToken),
+        "jl" => Box::new(JlToken),
+        "je" => Box::new(JeToken),
+        "pixel" => Box::new(PixelToken),
+        "fill" => Box::new(FillToken),
+        "shift" => Box::new(ShiftToken),
+        "rotate" => Box::new(RotateToken),
+        "show" => Box::new(ShowToken),
+        "hsv" => Box::new(HsvToken),
+        "hsl" => Box::new(HslToken),
+        "rgbhsv" => Box::new(RgbHsvToken),
+        "rgbhsl" => Box::new(RgbHslToken),
+        "pause" => Box::new(PauseToken),
+        "send" => Box::new(SendToken),
+        "status" => Box::new(StatusToken),
+        _ => return None,
+    };
+
+    Some(token)
+}
+
+/// Creates the token to load a label id into rgl
+fn load_label(id: u32) -> Box<dyn Token> {
+    if id <= 0xFF {
+        Box::new(SetToken {
+            value: id as u8,
+            register: RGL,
+        })
+    } else {
+        Box::new(SetwToken {
+            value: id,
+            register: RGL,
+        })
+    }
+}
+
+/// Parses a value that has to fit into the given number of bytes
+fn parse_immediate(word: &Word, size: usize) -> Result<u32, Diagnostic> {
+    let value = parse_value(word.text)
+        .map_err(|_| Diagnostic::new(word, format!("invalid number '{}'", word.text)))?;
+    if size < 4 && value >= 1 << (size * 8) {
+        return Err(Diagnostic::new(
+            word,
+            format!("the value {} doesn't fit into {} byte(s)", value, size),
+        ));
+    }
+
+    Ok(value)
+}
+
+/// Returns the name of a label without the trailing colon
+/// if the value is a valid label name
+fn get_label_name(value: &str) -> Option<&str> {
+    let name = value.strip_suffix(':').unwrap_or(value);
+    let mut chars = name.chars();
+    let first = chars.next()?;
+
+    if (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Returns a hint with the most similar candidate if there is one
+fn suggest<'a>(value: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    candidates
+        .map(|candidate| (edit_distance(value, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 2).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!("did you mean {}?", candidate))
+}
+
+/// Calculates the levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, char_a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, char_b) in b.iter().enumerate() {
+            let substitution = previous[j] + if char_a == *char_b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Parses a value depending on if it starts with 0x (as a hex value)
+/// or just is a plain base-10 number
+pub fn parse_value(value: &str) -> Result<u32, ParseIntError> {
+    if value.starts_with("0x") {
+        let value = value.trim_start_matches("0x");
+        u32::from_str_radix(value, 16)
+    } else {
+        value.parse::<u32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registers::{RCR, RGD};
+    use crate::tokens::{T_CMD, T_GOTO, T_JL, T_LABEL, T_SET, T_SETW};
+
+    fn code(source: &str) -> Vec<u8> {
+        assemble(source, true).unwrap()
+    }
+
+    #[test]
+    fn named_labels_skip_the_numeric_ids() {
+        let source = "label 0x00\nlabel first:\nlabel 0x01\nlabel second:\nlabel 3\nlabel third:\n";
+        let (container, _) = assemble_container(source, false).unwrap();
+        assert_eq!(
+            container.symbols,
+            vec![
+                (2, "first".to_string()),
+                (4, "second".to_string()),
+                (5, "third".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_load_the_label_into_rgl() {
+        assert_eq!(
+            code("goto end\nlabel 0x00\nlabel end:\njl 0x00\n"),
+            vec![
+                T_SET, 0x01, RGL, T_GOTO, T_LABEL, 0, 0, 0, 0, T_LABEL, 0, 0, 0, 1, T_SET, 0x00,
+                RGL, T_JL
+            ]
+        );
+    }
+
+    #[test]
+    fn label_ids_above_255_are_loaded_with_setw() {
+        assert_eq!(load_label(0xFF).to_bytecode(), vec![T_SET, 0xFF, RGL]);
+        assert_eq!(
+            load_label(0x0123_4567).to_bytecode(),
+            vec![T_SETW, 0x01, 0x23, 0x45, 0x67, RGL]
+        );
+
+        let mut source: String = (0..=0xFF).map(|id| format!("label {}\n", id)).collect();
+        source.push_str("label far:\ngoto far\n");
+        let code = code(&source);
+        assert_eq!(code[code.len() - 7..], [T_SETW, 0, 0, 1, 0, RGL, T_GOTO]);
+    }
+
+    #[test]
+    fn setw_needs_a_four_byte_register() {
+        assert_eq!(
+            code("setw 0x12345678 rgd\n"),
+            vec![T_SETW, 0x12, 0x34, 0x56, 0x78, RGD]
+        );
+
+        let diagnostics = assemble("setw 0x01 rcr\n", true).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 11));
+        assert_eq!(
+            diagnostics[0].hint.as_deref(),
+            Some("use set to write to rcr")
+        );
+        assert_eq!(code("set 0xFF rcr\n"), vec![T_SET, 0xFF, RCR]);
+        assert!(assemble("set 0x100 rcr\n", true).is_err());
+    }
+
+    #[test]
+    fn all_errors_are_collected() {
+        let source = "st 0x01 rcr\nset 0x01 rgx\ncopy rgd\ngoto lop\nlabel loop:\n";
+        let diagnostics = assemble(source, false).unwrap_err();
+        let positions: Vec<(usize, usize)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.column))
+            .collect();
+        let hints: Vec<Option<&str>> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.hint.as_deref())
+            .collect();
+
+        assert_eq!(positions, vec![(1, 1), (2, 10), (3, 9), (4, 6)]);
+        assert_eq!(
+            hints,
+            vec![
+                Some("did you mean set?"),
+                Some("did you mean rgd?"),
+                None,
+                Some("did you mean loop?")
+            ]
+        );
+    }
+
+    #[test]
+    fn diagnostics_highlight_the_token() {
+        let source = "set 0x01 rcr\n\tcopy rgd rgq\n";
+        let diagnostics = assemble(source, false).unwrap_err();
+        assert_eq!(
+            diagnostics[0].render("test.sasm", source),
+            "error: unknown register 'rgq'\n --> test.sasm:2:11\n  |\n2 | \tcopy rgd rgq\n  | \t         ^^^\n  = hint: did you mean rgd?"
+        );
+    }
+
+    #[test]
+    fn cmd_loads_the_program_and_speed() {
+        assert_eq!(
+            code("cmd seven_cross_fade 0x10\ncmd\n"),
+            vec![T_SET, 0x25, RGD, T_SET, 0x10, RGI, T_CMD, T_CMD]
+        );
+
+        let diagnostics =
+            assemble("cmd seven_cross_fade\ncmd seven_cross_fade 0x20\n", true).unwrap_err();
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "missing operand for 'cmd': expected a speed",
+                "the speed 0x20 is outside of 0x01 to 0x1F"
+            ]
+        );
+    }
+}
@@ -0,0 +1,790 @@
+use crate::clock::{real_clock, Clock};
+use crate::ledstrip_controller::{
+    create_status_response, decode_message, LedStripController, Pixel, StripMessage, StripStatus,
+    STATUS_QUERY,
+};
+use crate::protocols::protocol_from_name;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, IsTerminal, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A destination for the messages that are sent to the strip
+pub trait StripBackend: Debug {
+    fn send(&mut self, message: &[u8]) -> io::Result<()>;
+
+    /// Shows the pixels on an addressable strip
+    fn send_frame(&mut self, _pixels: &[Pixel]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the backend can't show frames",
+        ))
+    }
+
+    /// Reads a response of exactly the length of the buffer from the strip
+    fn receive(&mut self, _buffer: &mut [u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the backend can't read from the strip",
+        ))
+    }
+
+    /// Sets the clock for backends that add timestamps to the messages
+    fn set_clock(&mut self, _clock: Rc<dyn Clock>) {}
+
+    /// Returns if the connection dropped since the last call,
+    /// in which case the strip may show something else than what was sent
+    fn take_disconnected(&mut self) -> bool {
+        false
+    }
+}
+
+/// What happens to messages while the connection to the controller is down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkPolicy {
+    /// Waits until the connection is restored and sends the message afterwards
+    Block,
+    /// Discards the message and tries to reconnect with the next one
+    Drop,
+    /// Returns the error
+    Fault,
+}
+
+impl FromStr for LinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(LinkPolicy::Block),
+            "drop" => Ok(LinkPolicy::Drop),
+            "fault" => Ok(LinkPolicy::Fault),
+            _ => Err(format!("unknown link policy {}", s)),
+        }
+    }
+}
+
+/// The connection settings of the tcp backend
+#[derive(Debug, Clone)]
+pub struct TcpOptions {
+    pub connect_timeout: Duration,
+    /// The timeout for writing a message or reading a response
+    pub write_timeout: Duration,
+    /// The longest wait between two reconnect attempts
+    pub max_backoff: Duration,
+    pub link_policy: LinkPolicy,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(5),
+            link_policy: LinkPolicy::Block,
+        }
+    }
+}
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Sends the messages to the controller of the strip over tcp and
+/// reconnects with an increasing backoff if the connection drops
+#[derive(Debug)]
+pub struct TcpBackend {
+    address: String,
+    options: TcpOptions,
+    stream: Option<TcpStream>,
+    clock: Rc<dyn Clock>,
+    backoff: Duration,
+    /// The time of the clock when the next reconnect may be attempted
+    next_attempt: Duration,
+    disconnected: bool,
+}
+
+impl TcpBackend {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        Self::connect_with(address, TcpOptions::default())
+    }
+
+    /// Connects to the address with the given timeouts and link policy
+    pub fn connect_with(address: &str, options: TcpOptions) -> io::Result<Self> {
+        let mut backend = Self {
+            address: address.to_string(),
+            options,
+            stream: None,
+            clock: real_clock(),
+            backoff: MIN_BACKOFF,
+            next_attempt: Duration::from_secs(0),
+            disconnected: false,
+        };
+        backend.stream = Some(backend.open_stream()?);
+
+        Ok(backend)
+    }
+
+    /// Opens a connection trying every address the host resolves to
+    fn open_stream(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} doesn't resolve to an address", self.address),
+        );
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.options.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(self.options.write_timeout))?;
+                    stream.set_read_timeout(Some(self.options.write_timeout))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Tries to reconnect unless the backoff since the last attempt hasn't passed yet
+    fn reconnect(&mut self) -> io::Result<()> {
+        let now = self.clock.now();
+        if now < self.next_attempt {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("not connected to {}", self.address),
+            ));
+        }
+        match self.open_stream() {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.backoff = MIN_BACKOFF;
+                Ok(())
+            }
+            Err(e) => {
+                self.next_attempt = now + self.backoff;
+                self.backoff = (self.backoff * 2).min(self.options.max_backoff);
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the message over the current connection or reconnects once
+    fn try_send(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.reconnect()?;
+        }
+        let result = self.stream.as_mut().unwrap().write_all(message);
+        if result.is_err() {
+            self.stream = None;
+            self.disconnected = true;
+        }
+
+        result
+    }
+}
+
+impl StripBackend for TcpBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        loop {
+            match self.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(e) => match self.options.link_policy {
+                    LinkPolicy::Fault => return Err(e),
+                    LinkPolicy::Drop => return Ok(()),
+                    LinkPolicy::Block => self
+                        .clock
+                        .sleep(self.next_attempt.saturating_sub(self.clock.now())),
+                },
+            }
+        }
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        let address = &self.address;
+        let stream = self.stream.as_mut().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("not connected to {}", address),
+            )
+        })?;
+        let result = stream.read_exact(buffer);
+        if result.is_err() {
+            self.stream = None;
+            self.disconnected = true;
+        }
+
+        result
+    }
+
+    /// Waits for reconnects with the clock
+    fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.next_attempt = clock.now();
+        self.clock = clock;
+    }
+
+    fn take_disconnected(&mut self) -> bool {
+        std::mem::replace(&mut self.disconnected, false)
+    }
+}
+
+/// Sends every message as a single datagram to the controller
+#[derive(Debug)]
+pub struct UdpBackend {
+    socket: UdpSocket,
+}
+
+impl UdpBackend {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        Ok(Self { socket })
+    }
+}
+
+impl StripBackend for UdpBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        let length = self.socket.send(message)?;
+        if length != message.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "the message was truncated",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        let length = self.socket.recv(buffer)?;
+        if length != buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the response is incomplete",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes frames for a bridge of an addressable strip as the pixel count
+/// (2 bytes big endian) followed by the red, green and blue value of every pixel.
+/// Messages for the whole strip are ignored
+#[derive(Debug)]
+pub struct PixelBackend<W: Write + Debug> {
+    writer: W,
+}
+
+impl<W: Write + Debug> PixelBackend<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write + Debug> StripBackend for PixelBackend<W> {
+    fn send(&mut self, _: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn send_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
+        let count = u16::try_from(pixels.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "too many pixels for a frame")
+        })?;
+        let mut frame = count.to_be_bytes().to_vec();
+        for (r, g, b) in pixels {
+            frame.extend_from_slice(&[*r, *g, *b]);
+        }
+        self.writer.write_all(&frame)?;
+        self.writer.flush()
+    }
+}
+
+/// Keeps all messages in memory. Clones share the same recording
+#[derive(Debug, Clone, Default)]
+pub struct RecordingBackend {
+    frames: Rc<RefCell<Vec<Vec<u8>>>>,
+}
+
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all messages that have been sent so far
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        self.frames.borrow().clone()
+    }
+}
+
+impl StripBackend for RecordingBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.frames.borrow_mut().push(message.to_vec());
+
+        Ok(())
+    }
+}
+
+/// Writes every message with the time since the start in microseconds
+/// as a line of hex bytes into a file
+#[derive(Debug)]
+pub struct FileBackend {
+    writer: BufWriter<File>,
+    clock: Rc<dyn Clock>,
+}
+
+impl FileBackend {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            clock: real_clock(),
+        })
+    }
+}
+
+impl StripBackend for FileBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        let bytes: Vec<String> = message.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(
+            self.writer,
+            "{:>12} {}",
+            self.clock.now().as_micros(),
+            bytes.join(" ")
+        )?;
+        self.writer.flush()
+    }
+
+    fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+/// Prints the messages to stdout
+#[derive(Debug, Default)]
+pub struct StdoutBackend;
+
+impl StripBackend for StdoutBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        println!("Send: {:?}", message);
+
+        Ok(())
+    }
+
+    fn send_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
+        println!("Frame: {:?}", pixels);
+
+        Ok(())
+    }
+}
+
+/// Renders the state of the strip as a colored block in the terminal.
+/// Every message adds a line with the time since the start, the pause
+/// since the previous message and the current color and state
+#[derive(Debug)]
+pub struct SimulatorBackend {
+    clock: Rc<dyn Clock>,
+    last_message: Duration,
+    on: bool,
+    color: (u8, u8, u8),
+    /// Redraws a single line instead of printing one per message
+    redraw: bool,
+    drawn: bool,
+}
+
+impl SimulatorBackend {
+    pub fn new() -> Self {
+        Self {
+            clock: real_clock(),
+            last_message: Duration::from_secs(0),
+            on: false,
+            color: (0, 0, 0),
+            redraw: io::stdout().is_terminal(),
+            drawn: false,
+        }
+    }
+
+    /// Prints the line or replaces the previous one when stdout is a terminal
+    fn show(&mut self, line: &str) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if self.redraw {
+            self.drawn = true;
+            write!(stdout, "\r\x1b[2K{}", line)?;
+            stdout.flush()
+        } else {
+            writeln!(stdout, "{}", line)
+        }
+    }
+
+    /// Renders the block for the current color and state
+    fn render_strip(&self) -> String {
+        let (r, g, b) = self.color;
+        if self.on {
+            format!(
+                "\x1b[48;2;{};{};{}m{}\x1b[0m #{:02X}{:02X}{:02X} on ",
+                r,
+                g,
+                b,
+                " ".repeat(16),
+                r,
+                g,
+                b
+            )
+        } else {
+            format!("{} #{:02X}{:02X}{:02X} off", "░".repeat(16), r, g, b)
+        }
+    }
+}
+
+impl Default for SimulatorBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SimulatorBackend {
+    /// Moves the cursor below the redrawn line
+    fn drop(&mut self) {
+        if self.drawn {
+            println!();
+        }
+    }
+}
+
+impl StripBackend for SimulatorBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        if message == STATUS_QUERY {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        let pause = now.saturating_sub(self.last_message);
+        self.last_message = now;
+
+        let event = match decode_message(message) {
+            Some(StripMessage::Color(r, g, b)) => {
+                self.color = (r, g, b);
+                String::new()
+            }
+            Some(StripMessage::ColorWhite(r, g, b, w)) => {
+                self.color = (
+                    r.saturating_add(w),
+                    g.saturating_add(w),
+                    b.saturating_add(w),
+                );
+                String::new()
+            }
+            Some(StripMessage::State(on)) => {
+                self.on = on;
+                String::new()
+            }
+            Some(StripMessage::Program(program, speed)) => {
+                format!("program {} (speed 0x{:02X})", program.name(), speed)
+            }
+            None => format!("invalid message {:?}", message),
+        };
+        let timeline = "·".repeat((pause.as_millis() / 50).min(40) as usize);
+
+        let line = format!(
+            "{:>9.3}s {:>+8}ms {:<40} {} {}",
+            now.as_secs_f64(),
+            pause.as_millis(),
+            timeline,
+            self.render_strip(),
+            event
+        );
+
+        self.show(&line)
+    }
+
+    /// Renders every pixel as a colored cell
+    fn send_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
+        let cells: String = pixels
+            .iter()
+            .map(|(r, g, b)| format!("\x1b[48;2;{};{};{}m  ", r, g, b))
+            .collect();
+        let line = format!(
+            "{:>9.3}s frame {}\x1b[0m",
+            self.clock.now().as_secs_f64(),
+            cells
+        );
+
+        self.show(&line)
+    }
+
+    fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.last_message = clock.now();
+        self.clock = clock;
+    }
+
+    /// Answers status queries with the simulated state
+    fn receive(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        let (r, g, b) = self.color;
+        let response = create_status_response(&StripStatus {
+            on: self.on,
+            r,
+            g,
+            b,
+        });
+        if buffer.len() != response.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the simulator only answers status queries",
+            ));
+        }
+        buffer.copy_from_slice(&response);
+
+        Ok(())
+    }
+}
+
+/// Discards all messages
+#[derive(Debug, Default)]
+pub struct NullBackend;
+
+impl StripBackend for NullBackend {
+    fn send(&mut self, _: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn send_frame(&mut self, _: &[Pixel]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates a backend from a description that is one of `tcp:<address>:<port>`,
+/// `udp:<address>:<port>`, `file:<path>`, `pixels:tcp:<address>:<port>`,
+/// `pixels:file:<path>`, `sim`, `stdout` or `null`.
+/// The options are used for tcp connections
+pub fn backend_from_spec(spec: &str, options: &TcpOptions) -> io::Result<Box<dyn StripBackend>> {
+    let (kind, argument) = match spec.find(':') {
+        Some(index) => (&spec[..index], Some(&spec[index + 1..])),
+        None => (spec, None),
+    };
+
+    match (kind, argument) {
+        ("tcp", Some(address)) => Ok(Box::new(TcpBackend::connect_with(
+            address,
+            options.clone(),
+        )?)),
+        ("pixels", Some(target)) => match target.find(':').map(|i| target.split_at(i)) {
+            Some(("tcp", address)) => Ok(Box::new(PixelBackend::new(TcpStream::connect(
+                &address[1..],
+            )?))),
+            Some(("file", path)) => Ok(Box::new(PixelBackend::new(BufWriter::new(File::create(
+                &path[1..],
+            )?)))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid backend '{}'", spec),
+            )),
+        },
+        ("udp", Some(address)) => Ok(Box::new(UdpBackend::connect(address)?)),
+        ("file", Some(path)) => Ok(Box::new(FileBackend::create(path)?)),
+        ("sim", None) => Ok(Box::new(SimulatorBackend::new())),
+        ("stdout", None) => Ok(Box::new(StdoutBackend)),
+        ("null", None) => Ok(Box::new(NullBackend)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid backend '{}'", spec),
+        )),
+    }
+}
+
+/// Creates a controller from a backend description that can start with
+/// the name of the protocol, e.g. `rgbw:tcp:<address>:<port>`.
+/// Without a protocol the messages are encoded for magic home controllers
+pub fn controller_from_spec(spec: &str, options: &TcpOptions) -> io::Result<LedStripController> {
+    if let Some(index) = spec.find(':') {
+        if let Some(protocol) = protocol_from_name(&spec[..index]) {
+            let backend = backend_from_spec(&spec[index + 1..], options)?;
+            return Ok(LedStripController::with_protocol(backend, protocol));
+        }
+    }
+
+    Ok(LedStripController::new(backend_from_spec(spec, options)?))
+}
+
+/// Parses a strip definition of the form `<name>=<backend>`
+pub fn parse_strip_definition(definition: &str) -> Result<(String, String), String> {
+    let index = definition
+        .find('=')
+        .ok_or_else(|| format!("expected <name>=<backend> but got '{}'", definition))?;
+    let name = definition[..index].trim();
+    let spec = definition[index + 1..].trim();
+    if name.is_empty() || spec.is_empty() {
+        return Err(format!(
+            "expected <name>=<backend> but got '{}'",
+            definition
+        ));
+    }
+
+    Ok((name.to_string(), spec.to_string()))
+}
+
+/// Reads a strip config file with one `<name> = <backend>` definition
+/// per line. Empty lines and lines starting with # are ignored
+pub fn read_strip_config(path: &str) -> io::Result<Vec<(String, String)>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            parse_strip_definition(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const MESSAGE: [u8; 4] = [0x71, 0x23, 0x0f, 0xa3];
+
+    struct Link {
+        backend: TcpBackend,
+        address: String,
+        clock: Rc<VirtualClock>,
+    }
+
+    /// Connects a backend with a virtual clock and closes the connection
+    /// again until the backend notices that it's down
+    fn broken_link(link_policy: LinkPolicy) -> Link {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let options = TcpOptions {
+            link_policy,
+            ..TcpOptions::default()
+        };
+        let mut backend = TcpBackend::connect_with(&address, options).unwrap();
+        let clock = Rc::new(VirtualClock::new());
+        backend.set_clock(clock.clone());
+        let (peer, _) = listener.accept().unwrap();
+        drop(peer);
+        drop(listener);
+
+        for _ in 0..100 {
+            if backend.try_send(&MESSAGE).is_err() {
+                assert!(backend.stream.is_none());
+                return Link {
+                    backend,
+                    address,
+                    clock,
+                };
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the backend didn't notice the closed connection");
+    }
+
+    /// Reads one message from the next connection of the listener
+    fn receive(listener: &TcpListener) -> Vec<u8> {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut message = vec![0u8; MESSAGE.len()];
+        stream.read_exact(&mut message).unwrap();
+
+        message
+    }
+
+    #[test]
+    fn reconnects_after_the_backoff() {
+        let mut link = broken_link(LinkPolicy::Fault);
+        assert!(link.backend.take_disconnected());
+        assert!(link.backend.send(&MESSAGE).is_err());
+        assert_eq!(
+            link.backend.send(&MESSAGE).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        let listener = TcpListener::bind(&link.address).unwrap();
+        assert_eq!(
+            link.backend.send(&MESSAGE).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+        link.clock.advance(MIN_BACKOFF);
+        link.backend.send(&MESSAGE).unwrap();
+        assert_eq!(receive(&listener), MESSAGE);
+        assert_eq!(link.backend.backoff, MIN_BACKOFF);
+        assert!(!link.backend.take_disconnected());
+    }
+
+    #[test]
+    fn the_backoff_doubles_up_to_the_maximum() {
+        let mut link = broken_link(LinkPolicy::Fault);
+        let mut waits = Vec::new();
+
+        for _ in 0..8 {
+            let wait = link.backend.next_attempt - link.clock.now();
+            link.clock.advance(wait);
+            waits.push(wait.as_millis());
+            assert!(link.backend.send(&MESSAGE).is_err());
+        }
+        waits.push((link.backend.next_attempt - link.clock.now()).as_millis());
+        assert_eq!(waits, [0, 100, 200, 400, 800, 1600, 3200, 5000, 5000]);
+    }
+
+    #[test]
+    fn dropped_messages_are_discarded() {
+        let mut link = broken_link(LinkPolicy::Drop);
+        link.backend.send(&MESSAGE).unwrap();
+        link.backend.send(&MESSAGE).unwrap();
+        assert!(link.backend.stream.is_none());
+        assert_eq!(link.clock.now(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn blocked_messages_wait_with_the_clock() {
+        let mut link = broken_link(LinkPolicy::Block);
+        let address = link.address.clone();
+        let controller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            receive(&TcpListener::bind(address).unwrap())
+        });
+
+        link.backend.send(&MESSAGE).unwrap();
+        assert_eq!(controller.join().unwrap(), MESSAGE);
+        assert!(link.clock.now() >= MIN_BACKOFF);
+    }
+
+    #[test]
+    fn strip_definitions_need_a_name_and_backend() {
+        assert_eq!(
+            parse_strip_definition(" kitchen = tcp:10.0.0.2:5577 "),
+            Ok(("kitchen".to_string(), "tcp:10.0.0.2:5577".to_string()))
+        );
+        assert!(parse_strip_definition("kitchen").is_err());
+        assert!(parse_strip_definition("=sim").is_err());
+        assert!(parse_strip_definition("kitchen = ").is_err());
+    }
+
+    #[test]
+    fn strip_configs_skip_comments_and_empty_lines() {
+        let path = std::env::temp_dir().join(format!("strips-{}.conf", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "# strips\nkitchen = sim\n\n  hall=rgbw:udp:10.0.0.3:5577\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_strip_config(path).unwrap(),
+            vec![
+                ("kitchen".to_string(), "sim".to_string()),
+                ("hall".to_string(), "rgbw:udp:10.0.0.3:5577".to_string())
+            ]
+        );
+
+        std::fs::write(path, "kitchen = sim\nhall\n").unwrap();
+        let error = read_strip_config(path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("'hall'"));
+    }
+}
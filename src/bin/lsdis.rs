@@ -0,0 +1,47 @@
+use ledstrip_vm::container::Container;
+use ledstrip_vm::disassembler::disassemble;
+use std::fs::{read, write};
+use std::io;
+use std::process;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opts {
+    #[structopt(short = "i", name = "input")]
+    input_file: String,
+
+    /// Writes the assembly to stdout if not given
+    #[structopt(short = "o", name = "output")]
+    output_file: Option<String>,
+}
+
+fn main() -> io::Result<()> {
+    let opts: Opts = Opts::from_args();
+    let bytecode = read(&opts.input_file)?;
+
+    let assembly = match disassemble(&bytecode) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            eprintln!("Failed to disassemble {}: {}", opts.input_file, e);
+            process::exit(1);
+        }
+    };
+
+    let has_source_map = Container::is_container(&bytecode)
+        && Container::from_bytes(&bytecode).is_ok_and(|container| container.source_map.is_some());
+    if has_source_map {
+        eprintln!(
+            "Warning: {} has a source map that isn't part of the assembly. \
+             The output assembles into the same bytecode without it",
+            opts.input_file
+        );
+    }
+
+    if let Some(output_file) = opts.output_file {
+        write(output_file, assembly)?;
+    } else {
+        print!("{}", assembly);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use ledstrip_vm::fake_controller::FakeController;
+use std::io;
+use std::thread::sleep;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opts {
+    #[structopt(short = "a", name = "address", default_value = "127.0.0.1")]
+    ip: String,
+
+    #[structopt(short = "p", name = "port", default_value = "5577")]
+    port: usize,
+}
+
+fn main() -> io::Result<()> {
+    let opts: Opts = Opts::from_args();
+    let controller = FakeController::bind(&format!("{}:{}", opts.ip, opts.port))?;
+    println!("Listening on {}", controller.address());
+
+    let mut printed_commands = 0;
+    let mut printed_errors = 0;
+    loop {
+        let commands = controller.commands();
+        for command in &commands[printed_commands..] {
+            println!("{:>12?} {:?}", command.elapsed, command.message);
+        }
+        printed_commands = commands.len();
+
+        let errors = controller.errors();
+        for error in &errors[printed_errors..] {
+            println!("{:>12?} invalid message {:?}", error.elapsed, error.bytes);
+        }
+        printed_errors = errors.len();
+
+        sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,96 @@
+use ledstrip_vm::backends::{backend_from_spec, TcpOptions};
+use ledstrip_vm::trace::{diff_traces, label_counts, read_trace, TraceEvent};
+use std::io;
+use std::process;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+enum Opts {
+    /// Sends the recorded strip messages to a backend with the recorded timing
+    Replay {
+        #[structopt(short = "i", name = "input")]
+        input_file: String,
+
+        /// Where the messages are sent to (see lsvm --backend)
+        #[structopt(long, default_value = "sim")]
+        backend: String,
+
+        /// Sends the messages without waiting between them
+        #[structopt(long)]
+        instant: bool,
+    },
+    /// Finds the first event where two traces differ
+    Diff { left: String, right: String },
+    /// Prints how often every label was reached and how many instructions it ran
+    Counts {
+        #[structopt(short = "i", name = "input")]
+        input_file: String,
+    },
+}
+
+fn main() -> io::Result<()> {
+    match Opts::from_args() {
+        Opts::Replay {
+            input_file,
+            backend,
+            instant,
+        } => {
+            let entries = read_trace(&input_file)?;
+            let mut backend = match backend_from_spec(&backend, &TcpOptions::default()) {
+                Ok(backend) => backend,
+                Err(e) => {
+                    eprintln!("Failed to create the backend {}: {}", backend, e);
+                    process::exit(3);
+                }
+            };
+            let start = Instant::now();
+            for entry in entries {
+                if !instant {
+                    let time = Duration::from_micros(entry.time);
+                    sleep(time.saturating_sub(start.elapsed()));
+                }
+                match entry.event {
+                    TraceEvent::Message { bytes, .. } => backend.send(&bytes)?,
+                    TraceEvent::Frame { pixels, .. } => backend.send_frame(&pixels)?,
+                    TraceEvent::Fault(message) => println!("The program faulted: {}", message),
+                    _ => {}
+                }
+            }
+        }
+        Opts::Diff { left, right } => {
+            let (left_entries, right_entries) = (read_trace(&left)?, read_trace(&right)?);
+            match diff_traces(&left_entries, &right_entries) {
+                None => println!("The traces are identical"),
+                Some(divergence) => {
+                    println!("The traces diverge at event {}", divergence.event);
+                    if let Some(index) = divergence.instruction {
+                        println!("after instruction {:0>4}", index);
+                    }
+                    let describe = |entry: Option<_>| match entry {
+                        Some(entry) => format!("{}", entry),
+                        None => "end of trace".to_string(),
+                    };
+                    println!("{}: {}", left, describe(divergence.left));
+                    println!("{}: {}", right, describe(divergence.right));
+                    process::exit(1);
+                }
+            }
+        }
+        Opts::Counts { input_file } => {
+            let entries = read_trace(&input_file)?;
+            println!("{:<24} {:>10} {:>14}", "label", "reached", "instructions");
+            for count in label_counts(&entries) {
+                let id = count.id;
+                let name = count.name.unwrap_or_else(|| format!("0x{:X}", id));
+                println!(
+                    "{:<24} {:>10} {:>14}",
+                    name, count.reached, count.instructions
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,9 +1,22 @@
-use ledstrip_vm::runtime::Runtime;
-use std::fs::read;
+use ledstrip_vm::backends::{
+    controller_from_spec, parse_strip_definition, read_strip_config, LinkPolicy, TcpOptions,
+};
+use ledstrip_vm::clock::VirtualClock;
+use ledstrip_vm::debugger::Debugger;
+use ledstrip_vm::limiter::{LimitMode, LimiterOptions};
+use ledstrip_vm::runtime::{OverflowMode, Runtime};
+use ledstrip_vm::trace::TraceWriter;
+use std::fs::{read, write};
 use std::io;
-use std::time::Instant;
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
+const PARSE_ERROR_STATUS: i32 = 1;
+const FAULT_STATUS: i32 = 2;
+const BACKEND_ERROR_STATUS: i32 = 3;
+
 #[derive(StructOpt, Debug)]
 struct Opts {
     #[structopt(long)]
@@ -12,36 +25,236 @@ struct Opts {
     #[structopt(short = "i", name = "input")]
     input_file: String,
 
-    #[structopt(short = "a", name = "address")]
-    ip: String,
+    #[structopt(
+        short = "a",
+        name = "address",
+        required_unless_one = &["backend", "strip", "strips"]
+    )]
+    ip: Option<String>,
+
+    #[structopt(
+        short = "p",
+        name = "port",
+        required_unless_one = &["backend", "strip", "strips"]
+    )]
+    port: Option<usize>,
+
+    /// Where the strip messages are sent to instead of the address
+    /// (tcp:<address>:<port>, udp:<address>:<port>, file:<path>, sim, stdout or null).
+    /// Prefix it with rgbw: for controllers with a white channel
+    #[structopt(long)]
+    backend: Option<String>,
+
+    /// Adds a strip that can be selected with the rgs register (<name>=<backend>).
+    /// The strips are numbered in the order they're given starting after
+    /// the strip of the address or backend option
+    #[structopt(long, parse(try_from_str = parse_strip_definition))]
+    strip: Vec<(String, String)>,
+
+    /// Reads additional strips from a file with one <name> = <backend> per line
+    #[structopt(long)]
+    strips: Option<String>,
+
+    /// The timeout for connecting to the controller in milliseconds
+    #[structopt(long, default_value = "5000")]
+    connect_timeout: u64,
+
+    /// The timeout for sending a message to the controller in milliseconds
+    #[structopt(long, default_value = "5000")]
+    write_timeout: u64,
+
+    /// What happens to messages while the connection to the controller
+    /// is down (block, drop or fault)
+    #[structopt(long, default_value = "block")]
+    link_policy: LinkPolicy,
+
+    /// How arithmetic overflows are handled (wrap, saturate or trap).
+    /// Overrides the overflow directive of the program
+    #[structopt(long)]
+    overflow: Option<OverflowMode>,
+
+    /// The number of pixels in the frame buffer of addressable strips
+    #[structopt(long, default_value = "0")]
+    pixels: usize,
+
+    /// The maximum number of colors sent to a strip per second (0 is unlimited)
+    #[structopt(long, default_value = "0")]
+    max_fps: u32,
+
+    /// What happens to colors that are sent too early (block or latest)
+    #[structopt(long, default_value = "block")]
+    frame_mode: LimitMode,
+
+    /// Skips colors that the strip already shows
+    #[structopt(long)]
+    coalesce: bool,
+
+    /// Prints how many colors were sent, merged, dropped and delayed
+    #[structopt(long)]
+    frame_stats: bool,
+
+    /// Accepts bytecode without a container header
+    #[structopt(long)]
+    legacy: bool,
+
+    /// Records every executed instruction, register change, memory write
+    /// and strip message into the file (see lstrace)
+    #[structopt(long)]
+    trace: Option<String>,
 
-    #[structopt(short = "p", name = "port")]
-    port: usize,
+    /// Prints how often every instruction and label block was executed
+    /// and how much time was spent computing and in pause
+    #[structopt(long)]
+    profile: bool,
+
+    /// Writes the profile as folded stacks for flamegraph tools into the file
+    #[structopt(long)]
+    profile_folded: Option<String>,
+
+    /// Runs pauses and fades instantly in virtual time instead of waiting
+    #[structopt(long)]
+    virtual_clock: bool,
+
+    /// Runs the program in the interactive debugger
+    #[structopt(long)]
+    debugger: bool,
 }
 
 fn main() -> io::Result<()> {
     let opts: Opts = Opts::from_args();
     let bytecode = read(opts.input_file)?;
 
-    let mut runtime = Runtime::new(&opts.ip, opts.port);
+    let mut definitions = Vec::new();
+    match (opts.backend, opts.ip, opts.port) {
+        (Some(spec), _, _) => definitions.push(("main".to_string(), spec)),
+        (None, Some(ip), Some(port)) => {
+            definitions.push(("main".to_string(), format!("tcp:{}:{}", ip, port)))
+        }
+        _ => {}
+    };
+    definitions.extend(opts.strip);
+    if let Some(path) = opts.strips {
+        match read_strip_config(&path) {
+            Ok(config) => definitions.extend(config),
+            Err(e) => {
+                eprintln!("Failed to read the strip config {}: {}", path, e);
+                process::exit(BACKEND_ERROR_STATUS);
+            }
+        }
+    }
+
+    let tcp_options = TcpOptions {
+        connect_timeout: Duration::from_millis(opts.connect_timeout),
+        write_timeout: Duration::from_millis(opts.write_timeout),
+        link_policy: opts.link_policy,
+        ..TcpOptions::default()
+    };
+    let mut strips = Vec::new();
+    for (name, spec) in definitions {
+        match controller_from_spec(&spec, &tcp_options) {
+            Ok(controller) => strips.push((name, controller)),
+            Err(e) => {
+                eprintln!("Failed to create the backend {} for {}: {}", spec, name, e);
+                process::exit(BACKEND_ERROR_STATUS);
+            }
+        }
+    }
+
+    let mut runtime = Runtime::with_controllers(strips);
     let start = Instant::now();
     runtime.set_debug(opts.debug);
-    runtime.parse_bytecode(bytecode);
+    runtime.set_allow_legacy(opts.legacy);
+    runtime.set_pixel_count(opts.pixels);
+    runtime.set_frame_limit(LimiterOptions {
+        max_fps: opts.max_fps,
+        mode: opts.frame_mode,
+        coalesce: opts.coalesce,
+    });
+    if let Err(e) = runtime.parse_bytecode(&bytecode) {
+        eprintln!("Failed to parse bytecode: {}", e);
+        eprintln!("{}", hex_dump(&bytecode, e.offset()));
+        process::exit(PARSE_ERROR_STATUS);
+    }
+    if let Some(mode) = opts.overflow {
+        runtime.set_overflow_mode(mode);
+    }
+    if opts.virtual_clock {
+        runtime.set_clock(Rc::new(VirtualClock::new()));
+    }
+    if let Some(path) = opts.trace {
+        if let Err(fault) = runtime.set_trace(TraceWriter::create(&path)?) {
+            eprintln!("Failed to write the trace: {}", fault);
+            process::exit(FAULT_STATUS);
+        }
+    }
+    runtime.set_profiling(opts.profile || opts.profile_folded.is_some());
     println!("Parsing took {:?}\n", start.elapsed());
 
     let start = Instant::now();
-    match runtime.run() {
+    let result = if opts.debugger {
+        let mut debugger = Debugger::new(runtime);
+        let stdin = io::stdin();
+        let result = debugger.run_interactive(stdin.lock(), io::stdout())?;
+        runtime = debugger.into_runtime();
+        match result {
+            Some(result) => result,
+            None => return Ok(()),
+        }
+    } else {
+        runtime.run()
+    };
+    if let Some(report) = runtime.profile_report() {
+        if opts.profile {
+            println!("{}", report);
+        }
+        if let Some(path) = opts.profile_folded {
+            write(path, report.folded())?;
+        }
+    }
+    if opts.frame_stats {
+        for (name, stats) in runtime.frame_stats() {
+            println!("Frames of {}: {}", name, stats);
+        }
+    }
+    match result {
         Ok(code) => println!(
             "Runtime exited with code {} after {:?}",
             code,
             start.elapsed()
         ),
-        Err(e) => println!(
-            "Runtime exited with error {:?} after {:?}",
-            e,
-            start.elapsed()
-        ),
+        Err(fault) => {
+            eprintln!("Runtime faulted after {:?}: {}", start.elapsed(), fault);
+            process::exit(FAULT_STATUS);
+        }
     }
 
     Ok(())
 }
+
+/// Formats the bytes surrounding the given offset as a hex dump
+/// with the byte at the offset highlighted
+fn hex_dump(bytecode: &[u8], offset: usize) -> String {
+    let start = (offset / 16).saturating_sub(1) * 16;
+    let end = ((offset / 16 + 2) * 16).min(bytecode.len());
+
+    bytecode[start..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let line_offset = start + i * 16;
+            let bytes: Vec<String> = chunk
+                .iter()
+                .enumerate()
+                .map(|(j, byte)| {
+                    if line_offset + j == offset {
+                        format!("[{:02X}]", byte)
+                    } else {
+                        format!(" {:02X} ", byte)
+                    }
+                })
+                .collect();
+            format!("{:08X}: {}", line_offset, bytes.join(""))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,77 @@
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// The source of time for pauses, the frame limiter, fades and timestamps
+pub trait Clock: Debug {
+    /// Returns the time since the clock was created
+    fn now(&self) -> Duration;
+
+    /// Waits for the given duration
+    fn sleep(&self, duration: Duration);
+}
+
+/// Follows the system time and sleeps the current thread
+#[derive(Debug, Clone)]
+pub struct RealClock {
+    start: Instant,
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep(duration)
+    }
+}
+
+/// Starts at zero and only advances when something sleeps,
+/// so programs run as fast as possible with deterministic timestamps
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Cell<Duration>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the time forward by the duration
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+/// Returns a new real time clock that can be shared
+pub fn real_clock() -> Rc<dyn Clock> {
+    Rc::new(RealClock::new())
+}
@@ -0,0 +1,236 @@
+use crate::ledstrip_controller::Pixel;
+
+/// A hue in degrees (0 to 359) with a saturation and a value
+/// or lightness between 0 and 255
+pub type HueColor = (u32, u8, u8);
+
+/// Divides and rounds to the nearest integer
+fn div_round(dividend: u32, divisor: u32) -> u32 {
+    (dividend + divisor / 2) / divisor
+}
+
+/// Returns the rgb color for a hue, a chroma and the value that is added to
+/// all channels. Hues of 360 degrees and above wrap around
+fn from_chroma(hue: u32, chroma: u32, offset: u32) -> Pixel {
+    let hue = hue % 360;
+    let (sector, rest) = (hue / 60, hue % 60);
+    let rising = div_round(chroma * rest, 60);
+    let falling = div_round(chroma * (60 - rest), 60);
+    let (r, g, b) = match sector {
+        0 => (chroma, rising, 0),
+        1 => (falling, chroma, 0),
+        2 => (0, chroma, rising),
+        3 => (0, falling, chroma),
+        4 => (rising, 0, chroma),
+        _ => (chroma, 0, falling),
+    };
+    let channel = |value: u32| (value + offset).min(255) as u8;
+
+    (channel(r), channel(g), channel(b))
+}
+
+/// Returns the hue in degrees of an rgb color
+fn hue(color: Pixel) -> u32 {
+    let (r, g, b) = (color.0 as i32, color.1 as i32, color.2 as i32);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta == 0 {
+        return 0;
+    }
+    let (base, difference) = if max == r {
+        (0, g - b)
+    } else if max == g {
+        (120, b - r)
+    } else {
+        (240, r - g)
+    };
+    let offset = (60 * difference + difference.signum() * delta / 2) / delta;
+
+    (base + offset).rem_euclid(360) as u32
+}
+
+/// Converts a color with hue, saturation and value to rgb
+pub fn hsv_to_rgb((hue, saturation, value): HueColor) -> Pixel {
+    let value = value as u32;
+    let chroma = div_round(value * saturation as u32, 255);
+
+    from_chroma(hue, chroma, value - chroma)
+}
+
+/// Returns the largest chroma possible for the sum of the largest
+/// and the smallest channel
+fn lightness_range(sum: u32) -> u32 {
+    255 - (sum as i32 - 255).unsigned_abs()
+}
+
+/// Converts a color with hue, saturation and lightness to rgb
+pub fn hsl_to_rgb((hue, saturation, lightness): HueColor) -> Pixel {
+    let lightness = lightness as u32;
+    let chroma = |sum: u32| div_round(lightness_range(sum) * saturation as u32, 255);
+    // the sum of max and min is either 2l or the odd sum next to it (see `hsl_lightness`).
+    // The chroma (max - min) always has the same parity as the sum which tells them apart
+    let odd_sum = if lightness <= 128 {
+        (2 * lightness).checked_sub(1)
+    } else {
+        Some(2 * lightness + 1).filter(|sum| *sum < 510)
+    };
+    let sum = odd_sum
+        .filter(|sum| chroma(*sum) % 2 == 1)
+        .unwrap_or(2 * lightness);
+    let chroma = chroma(sum);
+
+    from_chroma(hue, chroma, (sum - chroma) / 2)
+}
+
+/// Converts an rgb color to hue, saturation and value
+pub fn rgb_to_hsv(color: Pixel) -> HueColor {
+    let max = color.0.max(color.1).max(color.2) as u32;
+    let min = color.0.min(color.1).min(color.2) as u32;
+    let saturation = if max == 0 {
+        0
+    } else {
+        div_round((max - min) * 255, max)
+    };
+
+    (hue(color), saturation as u8, max as u8)
+}
+
+/// Returns half of the sum of the largest and the smallest channel rounded
+/// towards the middle, so only black has a lightness of 0 and only white one of 255
+fn hsl_lightness(sum: u32) -> u32 {
+    if sum <= 255 {
+        div_round(sum, 2)
+    } else {
+        sum / 2
+    }
+}
+
+/// Converts an rgb color to hue, saturation and lightness
+pub fn rgb_to_hsl(color: Pixel) -> HueColor {
+    let max = color.0.max(color.1).max(color.2) as u32;
+    let min = color.0.min(color.1).min(color.2) as u32;
+    let range = lightness_range(max + min);
+    let saturation = if range == 0 {
+        0
+    } else {
+        div_round((max - min) * 255, range)
+    };
+
+    (hue(color), saturation as u8, hsl_lightness(max + min) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARIES: [(Pixel, u32); 6] = [
+        ((255, 0, 0), 0),
+        ((255, 255, 0), 60),
+        ((0, 255, 0), 120),
+        ((0, 255, 255), 180),
+        ((0, 0, 255), 240),
+        ((255, 0, 255), 300),
+    ];
+
+    /// Every color with the channels in steps of 3 including 0 and 255
+    fn sampled_colors() -> impl Iterator<Item = Pixel> {
+        (0..=255u8).step_by(3).flat_map(|r| {
+            (0..=255u8)
+                .step_by(3)
+                .flat_map(move |g| (0..=255u8).step_by(3).map(move |b| (r, g, b)))
+        })
+    }
+
+    fn max_min(color: Pixel) -> (u8, u8) {
+        (
+            color.0.max(color.1).max(color.2),
+            color.0.min(color.1).min(color.2),
+        )
+    }
+
+    fn distance(left: Pixel, right: Pixel) -> u8 {
+        let channel = |l: u8, r: u8| (l as i32 - r as i32).unsigned_abs() as u8;
+        channel(left.0, right.0)
+            .max(channel(left.1, right.1))
+            .max(channel(left.2, right.2))
+    }
+
+    #[test]
+    fn primaries_round_trip() {
+        for (color, hue) in PRIMARIES.iter() {
+            assert_eq!(rgb_to_hsv(*color), (*hue, 255, 255));
+            assert_eq!(rgb_to_hsl(*color), (*hue, 255, 128));
+            assert_eq!(hsv_to_rgb((*hue, 255, 255)), *color);
+            assert_eq!(hsl_to_rgb((*hue, 255, 128)), *color);
+        }
+        assert_eq!(hsl_to_rgb(rgb_to_hsl((1, 0, 0))), (1, 0, 0));
+        assert_eq!(hsl_to_rgb(rgb_to_hsl((255, 254, 254))), (255, 254, 254));
+    }
+
+    #[test]
+    fn greys_have_no_saturation() {
+        for value in 0..=255u8 {
+            let grey = (value, value, value);
+            assert_eq!(rgb_to_hsv(grey), (0, 0, value));
+            assert_eq!(rgb_to_hsl(grey), (0, 0, value));
+            for hue in [0, 120, 359].iter() {
+                assert_eq!(hsv_to_rgb((*hue, 0, value)), grey);
+                assert_eq!(hsl_to_rgb((*hue, 0, value)), grey);
+            }
+        }
+    }
+
+    #[test]
+    fn lightness_bounds_are_black_and_white() {
+        for saturation in 0..=255u8 {
+            for hue in (0..360).step_by(15) {
+                assert_eq!(hsl_to_rgb((hue, saturation, 0)), (0, 0, 0));
+                assert_eq!(hsl_to_rgb((hue, saturation, 255)), (255, 255, 255));
+                assert_eq!(hsv_to_rgb((hue, saturation, 0)), (0, 0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn hue_wraps_around() {
+        assert_eq!(hsv_to_rgb((360, 255, 255)), (255, 0, 0));
+        assert_eq!(hsl_to_rgb((360, 255, 128)), (255, 0, 0));
+        assert_eq!(hsv_to_rgb((720 + 120, 255, 255)), (0, 255, 0));
+        assert_eq!(hsv_to_rgb((359, 255, 255)), (255, 0, 4));
+        assert_eq!(hsl_to_rgb((359, 255, 128)), (255, 0, 4));
+        assert_eq!(rgb_to_hsv((255, 0, 4)), (359, 255, 255));
+        assert_eq!(rgb_to_hsv((255, 0, 1)), (0, 255, 255));
+    }
+
+    #[test]
+    fn hsv_round_trip_keeps_value_and_chroma() {
+        for color in sampled_colors() {
+            let back = hsv_to_rgb(rgb_to_hsv(color));
+            assert_eq!(max_min(back), max_min(color), "{:?}", color);
+            assert!(distance(back, color) <= 2, "{:?} -> {:?}", color, back);
+        }
+    }
+
+    #[test]
+    fn hsl_round_trip_is_close() {
+        for color in sampled_colors() {
+            let (hue, saturation, lightness) = rgb_to_hsl(color);
+            let back = hsl_to_rgb((hue, saturation, lightness));
+            let ((max, min), (back_max, back_min)) = (max_min(color), max_min(back));
+            // the lightness can't tell if max + min was odd or even and
+            // the middle lightness stands for three sums
+            let tolerance = if lightness == 128 { 2 } else { 1 };
+            assert!(
+                (max as i32 - back_max as i32).abs() <= tolerance,
+                "{:?}",
+                color
+            );
+            assert!(
+                (min as i32 - back_min as i32).abs() <= tolerance,
+                "{:?}",
+                color
+            );
+            assert!(distance(back, color) <= 4, "{:?} -> {:?}", color, back);
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use crate::errors::ParseError;
+use crate::runtime::OverflowMode;
+
+pub const MAGIC: [u8; 4] = *b"LSVM";
+pub const FORMAT_VERSION: u8 = 0x01;
+
+pub const S_CODE: u8 = 0x01;
+pub const S_DATA: u8 = 0x02;
+pub const S_SYMBOLS: u8 = 0x03;
+pub const S_SOURCE_MAP: u8 = 0x04;
+
+const HEADER_LENGTH: usize = 9;
+const SECTION_ENTRY_LENGTH: usize = 9;
+const CHECKSUM_LENGTH: usize = 4;
+
+/// A bytecode file with a header, a section table and a checksum.
+///
+/// The layout (all numbers big endian) is
+/// - magic `LSVM` (4 bytes)
+/// - format version (1 byte)
+/// - flags: overflow mode (1 byte, 0 if unset) and minimum vm version (2 bytes)
+/// - number of sections (1 byte)
+/// - section table entries: kind (1 byte), offset (4 bytes), length (4 bytes)
+/// - section contents: code, data, symbols and the optional source map
+///   (file name length (1 byte), file name, line of every instruction (4 bytes each))
+/// - crc32 of everything before the checksum (4 bytes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub overflow_mode: Option<OverflowMode>,
+    pub min_vm_version: u16,
+    pub code: Vec<u8>,
+    /// Initial memory values as address value pairs
+    pub data: Vec<(u32, u32)>,
+    /// Names of labels
+    pub symbols: Vec<(u32, String)>,
+    /// The source lines of the instructions
+    pub source_map: Option<SourceMap>,
+    /// The offset of the code section in the file the container was read from
+    pub code_offset: usize,
+}
+
+/// Maps the instructions to the lines of the assembly they were written on
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMap {
+    /// The name of the assembly file
+    pub file: String,
+    /// The line of every instruction in the order of the code
+    pub lines: Vec<u32>,
+}
+
+impl SourceMap {
+    /// Returns the file and line of the instruction at the index
+    pub fn location(&self, index: usize) -> Option<String> {
+        self.lines
+            .get(index)
+            .map(|line| format!("{}:{}", self.file, line))
+    }
+}
+
+impl Container {
+    pub fn new(code: Vec<u8>) -> Self {
+        Self {
+            overflow_mode: None,
+            min_vm_version: 0,
+            code,
+            data: Vec::new(),
+            symbols: Vec::new(),
+            source_map: None,
+            code_offset: 0,
+        }
+    }
+
+    /// Returns if the bytes start with the container magic number
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Serializes the container
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections = vec![(S_CODE, self.code.clone())];
+        if !self.data.is_empty() {
+            let mut data = Vec::new();
+            for (address, value) in &self.data {
+                data.append(&mut address.to_be_bytes().to_vec());
+                data.append(&mut value.to_be_bytes().to_vec());
+            }
+            sections.push((S_DATA, data));
+        }
+        if !self.symbols.is_empty() {
+            let mut symbols = Vec::new();
+            for (id, name) in &self.symbols {
+                let name = truncate_name(name);
+                symbols.append(&mut id.to_be_bytes().to_vec());
+                symbols.push(name.len() as u8);
+                symbols.append(&mut name.to_vec());
+            }
+            sections.push((S_SYMBOLS, symbols));
+        }
+        if let Some(source_map) = &self.source_map {
+            let file = truncate_name(&source_map.file);
+            let mut content = vec![file.len() as u8];
+            content.append(&mut file.to_vec());
+            for line in &source_map.lines {
+                content.append(&mut line.to_be_bytes().to_vec());
+            }
+            sections.push((S_SOURCE_MAP, content));
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.overflow_mode.map(|mode| mode as u8).unwrap_or(0));
+        bytes.append(&mut self.min_vm_version.to_be_bytes().to_vec());
+        bytes.push(sections.len() as u8);
+
+        let mut offset = HEADER_LENGTH + sections.len() * SECTION_ENTRY_LENGTH;
+        for (kind, content) in &sections {
+            bytes.push(*kind);
+            bytes.append(&mut (offset as u32).to_be_bytes().to_vec());
+            bytes.append(&mut (content.len() as u32).to_be_bytes().to_vec());
+            offset += content.len();
+        }
+        for (_, mut content) in sections {
+            bytes.append(&mut content);
+        }
+        let checksum = crc32(&bytes);
+        bytes.append(&mut checksum.to_be_bytes().to_vec());
+
+        bytes
+    }
+
+    /// Reads and validates a container
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        if !Self::is_container(bytes) {
+            return Err(ParseError::MissingHeader);
+        }
+        if bytes.len() < HEADER_LENGTH + CHECKSUM_LENGTH {
+            return Err(ParseError::MalformedContainer {
+                offset: bytes.len(),
+            });
+        }
+        let checksum_offset = bytes.len() - CHECKSUM_LENGTH;
+        let expected = read_u32(bytes, checksum_offset);
+        let actual = crc32(&bytes[..checksum_offset]);
+        if expected != actual {
+            return Err(ParseError::ChecksumMismatch {
+                offset: checksum_offset,
+                expected,
+                actual,
+            });
+        }
+        if bytes[4] != FORMAT_VERSION {
+            return Err(ParseError::UnsupportedVersion {
+                offset: 4,
+                version: bytes[4],
+            });
+        }
+        let overflow_mode = match bytes[5] {
+            0 => None,
+            code => Some(
+                OverflowMode::from_code(code)
+                    .ok_or(ParseError::MalformedContainer { offset: 5 })?,
+            ),
+        };
+        let mut container = Container::new(Vec::new());
+        container.overflow_mode = overflow_mode;
+        container.min_vm_version = u16::from_be_bytes([bytes[6], bytes[7]]);
+
+        let section_count = bytes[8] as usize;
+        let mut has_code = false;
+        for i in 0..section_count {
+            let entry_offset = HEADER_LENGTH + i * SECTION_ENTRY_LENGTH;
+            if entry_offset + SECTION_ENTRY_LENGTH > checksum_offset {
+                return Err(ParseError::MalformedContainer {
+                    offset: entry_offset,
+                });
+            }
+            let kind = bytes[entry_offset];
+            let offset = read_u32(bytes, entry_offset + 1) as usize;
+            let length = read_u32(bytes, entry_offset + 5) as usize;
+            let content = offset
+                .checked_add(length)
+                .filter(|end| *end <= checksum_offset)
+                .map(|end| &bytes[offset..end])
+                .ok_or(ParseError::MalformedContainer {
+                    offset: entry_offset,
+                })?;
+
+            match kind {
+                S_CODE => {
+                    has_code = true;
+                    container.code = content.to_vec();
+                    container.code_offset = offset;
+                }
+                S_DATA => {
+                    if !content.len().is_multiple_of(8) {
+                        return Err(ParseError::MalformedContainer { offset });
+                    }
+                    container.data = content
+                        .chunks(8)
+                        .map(|pair| (read_u32(pair, 0), read_u32(pair, 4)))
+                        .collect();
+                }
+                S_SYMBOLS => container.symbols = read_symbols(content, offset)?,
+                S_SOURCE_MAP => container.source_map = Some(read_source_map(content, offset)?),
+                _ => {}
+            }
+        }
+        if !has_code {
+            return Err(ParseError::MalformedContainer { offset: 8 });
+        }
+
+        Ok(container)
+    }
+}
+
+/// Reads the file name and the lines of a source map section
+fn read_source_map(content: &[u8], offset: usize) -> Result<SourceMap, ParseError> {
+    let malformed = ParseError::MalformedContainer { offset };
+    let length = *content.first().ok_or_else(|| malformed.clone())? as usize;
+    let file = content
+        .get(1..1 + length)
+        .ok_or_else(|| malformed.clone())?;
+    let file = String::from_utf8(file.to_vec()).map_err(|_| malformed.clone())?;
+    let lines = &content[1 + length..];
+    if !lines.len().is_multiple_of(4) {
+        return Err(malformed);
+    }
+
+    Ok(SourceMap {
+        file,
+        lines: lines.chunks(4).map(|line| read_u32(line, 0)).collect(),
+    })
+}
+
+/// Reads the entries of a symbol section
+fn read_symbols(content: &[u8], offset: usize) -> Result<Vec<(u32, String)>, ParseError> {
+    let mut symbols = Vec::new();
+    let mut position = 0;
+
+    while position < content.len() {
+        let malformed = ParseError::MalformedContainer {
+            offset: offset + position,
+        };
+        if position + 5 > content.len() {
+            return Err(malformed);
+        }
+        let id = read_u32(content, position);
+        let length = content[position + 4] as usize;
+        let name = content
+            .get(position + 5..position + 5 + length)
+            .ok_or_else(|| malformed.clone())?;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| malformed)?;
+        symbols.push((id, name));
+        position += 5 + length;
+    }
+
+    Ok(symbols)
+}
+
+/// Returns the bytes of the name cut to the 255 bytes that fit into
+/// the length byte without splitting a character
+fn truncate_name(name: &str) -> &[u8] {
+    let mut end = name.len().min(0xFF);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &name.as_bytes()[..end]
+}
+
+/// Reads a big endian u32 at the given position
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[position],
+        bytes[position + 1],
+        bytes[position + 2],
+        bytes[position + 3],
+    ])
+}
+
+/// Calculates the crc32 (IEEE) checksum of the data
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::NullBackend;
+    use crate::runtime::Runtime;
+    use crate::tokens::{T_ADD, T_SET};
+
+    fn container() -> Container {
+        let mut container = Container::new(vec![T_SET, 0x01, 0x05, T_ADD]);
+        container.overflow_mode = Some(OverflowMode::Trap);
+        container.min_vm_version = 2;
+        container.data = vec![(0x10, 0xDEAD_BEEF), (0x11, 0x01)];
+        container.symbols = vec![(1, "loop".to_string()), (3, "end".to_string())];
+        container.source_map = Some(SourceMap {
+            file: "example.sasm".to_string(),
+            lines: vec![1, 2],
+        });
+
+        container
+    }
+
+    /// Replaces the checksum after the bytes were modified
+    fn reseal(mut bytes: Vec<u8>) -> Vec<u8> {
+        let checksum_offset = bytes.len() - CHECKSUM_LENGTH;
+        let checksum = crc32(&bytes[..checksum_offset]);
+        bytes[checksum_offset..].copy_from_slice(&checksum.to_be_bytes());
+
+        bytes
+    }
+
+    #[test]
+    fn containers_survive_a_round_trip() {
+        let container = container();
+        let mut parsed = Container::from_bytes(&container.to_bytes()).unwrap();
+        assert_eq!(parsed.code_offset, HEADER_LENGTH + 4 * SECTION_ENTRY_LENGTH);
+        parsed.code_offset = 0;
+        assert_eq!(parsed, container);
+    }
+
+    #[test]
+    fn long_names_are_cut_at_a_character_boundary() {
+        let mut container = Container::new(Vec::new());
+        container.symbols = vec![(1, "ä".repeat(200))];
+        container.source_map = Some(SourceMap {
+            file: "ö".repeat(128),
+            lines: Vec::new(),
+        });
+        let parsed = Container::from_bytes(&container.to_bytes()).unwrap();
+
+        assert_eq!(parsed.symbols[0].1, "ä".repeat(127));
+        assert_eq!(parsed.source_map.unwrap().file, "ö".repeat(127));
+    }
+
+    #[test]
+    fn checksum_mismatches_are_rejected() {
+        let mut bytes = container().to_bytes();
+        let checksum_offset = bytes.len() - CHECKSUM_LENGTH;
+        bytes[HEADER_LENGTH + 4 * SECTION_ENTRY_LENGTH] ^= 0xFF;
+
+        match Container::from_bytes(&bytes) {
+            Err(ParseError::ChecksumMismatch {
+                offset,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(offset, checksum_offset);
+                assert_ne!(expected, actual);
+            }
+            other => panic!("expected a checksum mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_wrong_magic_number_is_rejected() {
+        let mut bytes = container().to_bytes();
+        bytes[3] = b'X';
+        assert_eq!(
+            Container::from_bytes(&bytes).unwrap_err(),
+            ParseError::MissingHeader
+        );
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        let bytes = container().to_bytes();
+        assert_eq!(
+            Container::from_bytes(&bytes[..7]).unwrap_err(),
+            ParseError::MalformedContainer { offset: 7 }
+        );
+
+        let mut header = bytes[..HEADER_LENGTH].to_vec();
+        header.append(&mut crc32(&header).to_be_bytes().to_vec());
+        assert_eq!(
+            Container::from_bytes(&header).unwrap_err(),
+            ParseError::MalformedContainer {
+                offset: HEADER_LENGTH
+            }
+        );
+    }
+
+    #[test]
+    fn sections_past_the_checksum_are_rejected() {
+        let bytes = container().to_bytes();
+        let length_position = HEADER_LENGTH + 5;
+        let offset_position = HEADER_LENGTH + 1;
+        let checksum_offset = bytes.len() - CHECKSUM_LENGTH;
+
+        let mut long = bytes.clone();
+        long[length_position..length_position + 4]
+            .copy_from_slice(&(checksum_offset as u32).to_be_bytes());
+        let mut moved = bytes.clone();
+        moved[offset_position..offset_position + 4]
+            .copy_from_slice(&(checksum_offset as u32 - 2).to_be_bytes());
+        let mut overflowing = bytes;
+        overflowing[offset_position..offset_position + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        for bytes in [long, moved, overflowing] {
+            assert_eq!(
+                Container::from_bytes(&reseal(bytes)).unwrap_err(),
+                ParseError::MalformedContainer {
+                    offset: HEADER_LENGTH
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_bytecode_has_to_be_allowed() {
+        let bytecode = [T_SET, 0x01, 0x05, T_ADD];
+        let mut runtime = Runtime::new(Box::new(NullBackend));
+        assert_eq!(
+            runtime.parse_bytecode(&bytecode).unwrap_err(),
+            ParseError::MissingHeader
+        );
+
+        runtime.set_allow_legacy(true);
+        assert_eq!(runtime.parse_bytecode(&bytecode), Ok(()));
+    }
+}
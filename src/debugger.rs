@@ -0,0 +1,586 @@
+use crate::assembler::parse_value;
+use crate::disassembler::format_instruction;
+use crate::errors::VmFault;
+use crate::registers::{get_register_code_by_name, get_register_name_by_code, REGISTER_NAMES};
+use crate::runtime::Runtime;
+use crate::tokens::mnemonic;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+step                      executes the next instruction
+next                      runs until the next instruction or the target of a jump
+continue                  runs until a breakpoint, a watchpoint or the end
+until <label>             runs until the label is reached
+break <index>             stops before the instruction at the index
+break label <label>       stops when the label is reached
+break opcode <opcode>     stops before every instruction with the mnemonic or opcode
+watch <register>          stops when the register changes
+watch mem <address>       stops when the memory at the address changes
+delete <number>           removes a breakpoint or watchpoint
+info                      lists the breakpoints and watchpoints
+regs                      prints all registers
+print <register>          prints a register
+mem <address>             prints the memory at the address
+set <register> <value>    writes a value to a register
+setmem <address> <value>  writes a value to the memory at the address
+list [count]              prints the next instructions
+skip-pause                toggles if pause instructions return immediately
+quit                      stops the program
+help                      prints this help";
+
+/// Where the execution is stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Breakpoint {
+    /// Before the instruction at the index
+    Index(usize),
+    /// When the label with the id is reached
+    Label(u32),
+    /// Before every instruction with the opcode
+    Opcode(u8),
+}
+
+/// What is watched for changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watchpoint {
+    Register(u8),
+    Memory(u32),
+}
+
+/// Why the execution stopped
+#[derive(Debug)]
+pub enum Stop {
+    /// A single instruction was executed or the target was reached
+    Paused,
+    Breakpoint(Breakpoint),
+    Watchpoint(Watchpoint, Option<u32>, Option<u32>),
+    Exited(u8),
+    Faulted(VmFault),
+}
+
+/// Runs a program one instruction at a time
+pub struct Debugger {
+    runtime: Runtime,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<(Watchpoint, Option<u32>)>,
+    skip_pause: bool,
+}
+
+impl Debugger {
+    pub fn new(runtime: Runtime) -> Self {
+        Self {
+            runtime,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            skip_pause: false,
+        }
+    }
+
+    /// Returns the runtime that is debugged
+    pub fn into_runtime(self) -> Runtime {
+        self.runtime
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        let value = self.watched_value(watchpoint);
+        self.watchpoints.push((watchpoint, value));
+    }
+
+    /// Sets if pause instructions return immediately
+    pub fn set_skip_pause(&mut self, skip_pause: bool) {
+        self.skip_pause = skip_pause;
+        self.runtime.set_skip_pause(skip_pause);
+    }
+
+    /// Executes the next instruction
+    pub fn step(&mut self) -> Stop {
+        self.execute(|_, _| true)
+    }
+
+    /// Runs until the instruction after the current one or the target of a jump is reached
+    pub fn step_over(&mut self) -> Stop {
+        let target = self.runtime.current_index() + 1;
+        self.execute(|runtime, previous| {
+            let index = runtime.current_index();
+            index == target || index != previous + 1
+        })
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit or the program ends
+    pub fn resume(&mut self) -> Stop {
+        self.execute(|_, _| false)
+    }
+
+    /// Runs until the label is reached. Fails if the program has no such label
+    pub fn run_to_label(&mut self, label: u32) -> Result<Stop, String> {
+        let index = self.label_index(label)?;
+        Ok(self.execute(|runtime, previous| label_reached(runtime, index, previous)))
+    }
+
+    /// Returns the index of the label instruction
+    fn label_index(&self, label: u32) -> Result<usize, String> {
+        self.runtime
+            .labels
+            .get(&label)
+            .copied()
+            .ok_or_else(|| format!("There is no label 0x{:X}", label))
+    }
+
+    /// Executes instructions until the target is reached or something stops the execution.
+    /// The target is checked with the runtime and the index of the instruction that was
+    /// executed. At least one instruction is executed so a breakpoint at the current
+    /// instruction doesn't stop the execution again
+    fn execute(&mut self, reached: impl Fn(&Runtime, usize) -> bool) -> Stop {
+        loop {
+            let previous = self.runtime.current_index();
+            match self.runtime.step() {
+                Ok(Some(code)) => return Stop::Exited(code),
+                Err(fault) => return Stop::Faulted(fault),
+                Ok(None) => {}
+            }
+            if let Some(stop) = self.check_watchpoints() {
+                return stop;
+            }
+            if let Some(breakpoint) = self.hit_breakpoint(previous) {
+                return Stop::Breakpoint(breakpoint);
+            }
+            if reached(&self.runtime, previous) {
+                return Stop::Paused;
+            }
+        }
+    }
+
+    /// Returns the first watchpoint whose value changed and records the new values
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        let mut stop = None;
+        for i in 0..self.watchpoints.len() {
+            let (watchpoint, old) = self.watchpoints[i];
+            let new = self.watched_value(watchpoint);
+            if new != old {
+                self.watchpoints[i].1 = new;
+                stop = stop.or(Some(Stop::Watchpoint(watchpoint, old, new)));
+            }
+        }
+
+        stop
+    }
+
+    /// Returns the breakpoint of the instruction that is executed next
+    /// after the instruction at the previous index
+    fn hit_breakpoint(&self, previous: usize) -> Option<Breakpoint> {
+        let index = self.runtime.current_index();
+        let opcode = self.runtime.instruction(index).map(|code| code[0]);
+
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| match breakpoint {
+                Breakpoint::Index(i) => *i == index,
+                Breakpoint::Label(label) => match self.runtime.labels.get(label) {
+                    Some(start) => label_reached(&self.runtime, *start, previous),
+                    None => false,
+                },
+                Breakpoint::Opcode(code) => opcode == Some(*code),
+            })
+            .copied()
+    }
+
+    fn watched_value(&mut self, watchpoint: Watchpoint) -> Option<u32> {
+        match watchpoint {
+            Watchpoint::Register(code) => self.runtime.read_register(code).ok(),
+            Watchpoint::Memory(address) => {
+                Some(self.runtime.memory.get(&address).copied().unwrap_or(0))
+            }
+        }
+    }
+
+    /// Formats the instruction at the index as assembly
+    pub fn format_instruction(&self, index: usize) -> Option<String> {
+        let code = self.runtime.instruction(index)?;
+        let symbols: Vec<(u32, String)> = self
+            .runtime
+            .symbols
+            .iter()
+            .map(|(id, name)| (*id, name.clone()))
+            .collect();
+
+        format_instruction(&code, 0, &symbols).ok()
+    }
+
+    /// Reads commands until the program ends or the session is quit.
+    /// Returns the exit code or the fault of the program
+    pub fn run_interactive(
+        &mut self,
+        input: impl BufRead,
+        mut output: impl Write,
+    ) -> io::Result<Option<Result<u8, VmFault>>> {
+        self.print_location(&mut output)?;
+        write!(output, "(lsdb) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.first() == Some(&"quit") {
+                return Ok(None);
+            }
+            let stop = match self.command(&words) {
+                Ok(Some(stop)) => Some(stop),
+                Ok(None) => None,
+                Err(message) => {
+                    writeln!(output, "{}", message)?;
+                    None
+                }
+            };
+            match stop {
+                Some(Stop::Exited(code)) => {
+                    writeln!(output, "Program exited with code {}", code)?;
+                    return Ok(Some(Ok(code)));
+                }
+                Some(Stop::Faulted(fault)) => {
+                    writeln!(output, "Program faulted: {}", fault)?;
+                    return Ok(Some(Err(fault)));
+                }
+                Some(Stop::Breakpoint(breakpoint)) => {
+                    writeln!(
+                        output,
+                        "Breakpoint {}",
+                        self.describe_breakpoint(breakpoint)
+                    )?;
+                    self.print_location(&mut output)?;
+                }
+                Some(Stop::Watchpoint(watchpoint, old, new)) => {
+                    writeln!(
+                        output,
+                        "Watchpoint {}: {} -> {}",
+                        self.describe_watchpoint(watchpoint),
+                        format_value(old),
+                        format_value(new)
+                    )?;
+                    self.print_location(&mut output)?;
+                }
+                Some(Stop::Paused) => self.print_location(&mut output)?,
+                None => self.print_output(&words, &mut output)?,
+            }
+            write!(output, "(lsdb) ")?;
+            output.flush()?;
+        }
+
+        Ok(None)
+    }
+
+    /// Executes a command that changes the state of the debugger
+    fn command(&mut self, words: &[&str]) -> Result<Option<Stop>, String> {
+        match words {
+            ["step"] | ["s"] => Ok(Some(self.step())),
+            ["next"] | ["n"] => Ok(Some(self.step_over())),
+            ["continue"] | ["c"] => Ok(Some(self.resume())),
+            ["until", label] => {
+                let label = self.parse_label(label)?;
+                self.run_to_label(label).map(Some)
+            }
+            ["break", "label", label] => {
+                let label = self.parse_label(label)?;
+                self.label_index(label)?;
+                self.add_breakpoint(Breakpoint::Label(label));
+                Ok(None)
+            }
+            ["break", "opcode", opcode] => {
+                let opcode = parse_opcode(opcode)?;
+                self.add_breakpoint(Breakpoint::Opcode(opcode));
+                Ok(None)
+            }
+            ["break", index] => {
+                let index = parse_number(index)? as usize;
+                self.add_breakpoint(Breakpoint::Index(index));
+                Ok(None)
+            }
+            ["watch", "mem", address] => {
+                let address = parse_number(address)?;
+                self.add_watchpoint(Watchpoint::Memory(address));
+                Ok(None)
+            }
+            ["watch", register] => {
+                let register = parse_register(register)?;
+                self.add_watchpoint(Watchpoint::Register(register));
+                Ok(None)
+            }
+            ["delete", number] => {
+                let number = parse_number(number)? as usize;
+                if number < self.breakpoints.len() {
+                    self.breakpoints.remove(number);
+                } else if number - self.breakpoints.len() < self.watchpoints.len() {
+                    self.watchpoints.remove(number - self.breakpoints.len());
+                } else {
+                    return Err(format!("There is no breakpoint or watchpoint {}", number));
+                }
+                Ok(None)
+            }
+            ["set", register, value] => {
+                let register = parse_register(register)?;
+                let value = parse_number(value)?;
+                self.runtime
+                    .write_register(register, value)
+                    .map_err(|fault| fault.to_string())?;
+                self.refresh_watchpoints();
+                Ok(None)
+            }
+            ["setmem", address, value] => {
+                let address = parse_number(address)?;
+                let value = parse_number(value)?;
+                self.runtime.memory.insert(address, value);
+                self.refresh_watchpoints();
+                Ok(None)
+            }
+            ["skip-pause"] => {
+                self.set_skip_pause(!self.skip_pause);
+                Ok(None)
+            }
+            ["print", register] => parse_register(register).map(|_| None),
+            ["mem", address] => parse_number(address).map(|_| None),
+            ["list"] | ["list", _] | ["info"] | ["regs"] | ["help"] | [] => Ok(None),
+            _ => Err(format!("Unknown command {}, see help", words.join(" "))),
+        }
+    }
+
+    /// Prints the output of commands that don't execute the program
+    fn print_output(&mut self, words: &[&str], output: &mut impl Write) -> io::Result<()> {
+        match words {
+            ["print", register] => {
+                if let Ok(code) = parse_register(register) {
+                    let value = self.runtime.read_register(code).ok();
+                    writeln!(output, "{} = {}", register, format_value(value))?;
+                }
+            }
+            ["mem", address] => {
+                if let Ok(address) = parse_number(address) {
+                    let value = self.runtime.memory.get(&address).copied().unwrap_or(0);
+                    writeln!(output, "0x{:X} = {}", address, format_value(Some(value)))?;
+                }
+            }
+            ["regs"] => writeln!(output, "{}", self.runtime.snapshot())?,
+            ["list"] | ["list", _] => {
+                let count = words
+                    .get(1)
+                    .and_then(|count| parse_number(count).ok())
+                    .unwrap_or(5) as usize;
+                let start = self.runtime.current_index();
+                for index in start..(start + count).min(self.runtime.instruction_count()) {
+                    self.print_instruction(index, output)?;
+                }
+            }
+            ["info"] => {
+                for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                    writeln!(
+                        output,
+                        "{}: break {}",
+                        i,
+                        self.describe_breakpoint(*breakpoint)
+                    )?;
+                }
+                for (i, (watchpoint, _)) in self.watchpoints.iter().enumerate() {
+                    writeln!(
+                        output,
+                        "{}: watch {}",
+                        self.breakpoints.len() + i,
+                        self.describe_watchpoint(*watchpoint)
+                    )?;
+                }
+                writeln!(output, "skip pause: {}", self.skip_pause)?;
+            }
+            ["skip-pause"] => writeln!(output, "skip pause: {}", self.skip_pause)?,
+            ["help"] => writeln!(output, "{}", HELP)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn print_location(&self, output: &mut impl Write) -> io::Result<()> {
+        let index = self.runtime.current_index();
+        if index < self.runtime.instruction_count() {
+            self.print_instruction(index, output)
+        } else {
+            writeln!(output, "At the end of the program")
+        }
+    }
+
+    fn print_instruction(&self, index: usize, output: &mut impl Write) -> io::Result<()> {
+        let instruction = self.format_instruction(index).unwrap_or_default();
+        match self.runtime.source_location(index) {
+            Some(location) => writeln!(output, "{:0>4}: {:<23} # {}", index, instruction, location),
+            None => writeln!(output, "{:0>4}: {}", index, instruction),
+        }
+    }
+
+    /// Records the current values of the watchpoints
+    /// so changes made by the debugger don't stop the execution
+    fn refresh_watchpoints(&mut self) {
+        for i in 0..self.watchpoints.len() {
+            self.watchpoints[i].1 = self.watched_value(self.watchpoints[i].0);
+        }
+    }
+
+    /// Parses a label id or the name of a label
+    fn parse_label(&self, label: &str) -> Result<u32, String> {
+        self.runtime
+            .symbols
+            .iter()
+            .find(|(_, name)| name.as_str() == label)
+            .map(|(id, _)| *id)
+            .map_or_else(|| parse_number(label), Ok)
+    }
+
+    fn describe_breakpoint(&self, breakpoint: Breakpoint) -> String {
+        match breakpoint {
+            Breakpoint::Index(index) => format!("at {}", index),
+            Breakpoint::Label(label) => match self.runtime.symbols.get(&label) {
+                Some(name) => format!("at label {}", name),
+                None => format!("at label 0x{:X}", label),
+            },
+            Breakpoint::Opcode(opcode) => match mnemonic(opcode) {
+                Some(name) => format!("on {}", name),
+                None => format!("on 0x{:02X}", opcode),
+            },
+        }
+    }
+
+    fn describe_watchpoint(&self, watchpoint: Watchpoint) -> String {
+        match watchpoint {
+            Watchpoint::Register(code) => {
+                get_register_name_by_code(code).unwrap_or("?").to_string()
+            }
+            Watchpoint::Memory(address) => format!("mem 0x{:X}", address),
+        }
+    }
+}
+
+/// Returns if the label at the index was reached by the last instruction.
+/// Jumps continue after the label instruction so the instruction after it
+/// counts unless the label instruction was just executed
+fn label_reached(runtime: &Runtime, start: usize, previous: usize) -> bool {
+    let index = runtime.current_index();
+    index == start || (index == start + 1 && previous != start)
+}
+
+fn format_value(value: Option<u32>) -> String {
+    match value {
+        Some(value) => format!("{} (0x{:X})", value, value),
+        None => "-".to_string(),
+    }
+}
+
+fn parse_number(value: &str) -> Result<u32, String> {
+    parse_value(value).map_err(|_| format!("{} is not a number", value))
+}
+
+fn parse_register(name: &str) -> Result<u8, String> {
+    get_register_code_by_name(name).ok_or_else(|| {
+        let names: Vec<&str> = REGISTER_NAMES.iter().map(|(name, _)| *name).collect();
+        format!(
+            "Unknown register {}, expected one of {}",
+            name,
+            names.join(", ")
+        )
+    })
+}
+
+/// Parses a mnemonic or an opcode
+fn parse_opcode(value: &str) -> Result<u8, String> {
+    (0..=0xFF)
+        .find(|opcode| mnemonic(*opcode) == Some(value))
+        .map_or_else(
+            || {
+                parse_number(value)
+                    .ok()
+                    .filter(|opcode| mnemonic(*opcode as u8).is_some() && *opcode <= 0xFF)
+                    .map(|opcode| opcode as u8)
+                    .ok_or_else(|| format!("Unknown opcode {}", value))
+            },
+            Ok,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::backends::NullBackend;
+    use crate::registers::RCR;
+
+    /// Counts rcr up to 4 in a loop that jumps back to label 1 three times
+    const LOOP: &str = "\
+set 0x00 rcr
+label 0x01
+copy rcr rgd
+set 0x01 rgi
+add
+copy rgo rcr
+copy rcr rgd
+set 0x04 rgi
+set 0x01 rgl
+jl
+label 0x02
+set 0x02 rcg
+";
+
+    fn debugger() -> Debugger {
+        let mut runtime = Runtime::new(Box::new(NullBackend));
+        runtime
+            .parse_bytecode(&assemble(LOOP, false).unwrap())
+            .unwrap();
+
+        Debugger::new(runtime)
+    }
+
+    #[test]
+    fn label_breakpoints_stop_on_every_iteration() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint(Breakpoint::Label(0x01));
+        for count in 0..4 {
+            assert!(matches!(debugger.resume(), Stop::Breakpoint(_)));
+            assert_eq!(debugger.runtime.read_register(RCR).unwrap(), count);
+        }
+        assert!(matches!(debugger.resume(), Stop::Exited(0)));
+    }
+
+    #[test]
+    fn until_stops_at_jumps_to_the_label() {
+        let mut debugger = debugger();
+        for _ in 0..4 {
+            assert!(matches!(debugger.run_to_label(0x01), Ok(Stop::Paused)));
+        }
+        assert!(matches!(debugger.run_to_label(0x01), Ok(Stop::Exited(0))));
+    }
+
+    #[test]
+    fn until_fails_for_unknown_labels() {
+        let mut debugger = debugger();
+        assert!(debugger.run_to_label(0x07).is_err());
+        assert_eq!(debugger.runtime.current_index(), 0);
+        assert!(debugger.command(&["break", "label", "0x07"]).is_err());
+    }
+
+    #[test]
+    fn next_stops_at_the_target_of_a_jump() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint(Breakpoint::Index(9));
+        assert!(matches!(debugger.resume(), Stop::Breakpoint(_)));
+        assert!(matches!(debugger.step_over(), Stop::Paused));
+        assert_eq!(debugger.runtime.current_index(), 2);
+    }
+
+    #[test]
+    fn steps_after_a_fault_keep_reporting_it() {
+        let mut runtime = Runtime::new(Box::new(NullBackend));
+        runtime
+            .parse_bytecode(&assemble("set 0x05 rgd\nclear rgi\ndiv\n", false).unwrap())
+            .unwrap();
+        let mut debugger = Debugger::new(runtime);
+
+        assert!(matches!(debugger.resume(), Stop::Faulted(_)));
+        assert!(matches!(debugger.step(), Stop::Faulted(_)));
+        assert!(matches!(debugger.resume(), Stop::Faulted(_)));
+    }
+}
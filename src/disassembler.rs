@@ -0,0 +1,130 @@
+use crate::container::Container;
+use crate::errors::ParseError;
+use crate::fade::{Easing, FADE_BACKGROUND};
+use crate::registers::get_register_name_by_code;
+use crate::runtime::OverflowMode;
+use crate::tokens::{
+    instruction_length, mnemonic, T_CLEAR, T_COPY, T_EXIT, T_FADE, T_LABEL, T_OVERFLOW, T_PRINT,
+    T_SET, T_SETW,
+};
+
+/// Decodes bytecode into assembly that can be assembled into the same bytecode again.
+/// Containers are written as directives and instructions, plain bytecode
+/// has to be assembled with `lsambler --legacy`. The source map section is dropped
+/// since it refers to the lines of the original assembly
+pub fn disassemble(bytecode: &[u8]) -> Result<String, ParseError> {
+    let mut lines = Vec::new();
+
+    if Container::is_container(bytecode) {
+        let container = Container::from_bytes(bytecode)?;
+        if let Some(mode) = container.overflow_mode {
+            lines.push(format!(".overflow {}", mode.name()));
+        }
+        for (address, value) in &container.data {
+            lines.push(format!(".data 0x{:X} 0x{:X}", address, value));
+        }
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.append(&mut disassemble_text(
+            &container.code,
+            container.code_offset,
+            &container.symbols,
+        )?);
+    } else {
+        lines.append(&mut disassemble_text(bytecode, 0, &[])?);
+    }
+    let mut assembly = lines.join("\n");
+    assembly.push('\n');
+
+    Ok(assembly)
+}
+
+/// Decodes raw bytecode into one line per instruction.
+/// The base offset is added to the offsets in the comments
+/// and labels with a symbol are written with their name
+fn disassemble_text(
+    bytecode: &[u8],
+    base_offset: usize,
+    symbols: &[(u32, String)],
+) -> Result<Vec<String>, ParseError> {
+    let mut lines = Vec::new();
+    let mut position = 0;
+
+    while position < bytecode.len() {
+        let offset = base_offset + position;
+        let opcode = bytecode[position];
+        let length =
+            instruction_length(opcode).ok_or(ParseError::UnknownOpcode { offset, opcode })?;
+        let code = bytecode
+            .get(position..position + length)
+            .ok_or(ParseError::TruncatedOperand { offset, opcode })?;
+        let instruction = format_instruction(code, offset, symbols)?;
+        lines.push(format!("{:<23} # 0x{:04X}", instruction, offset));
+        position += length;
+    }
+
+    Ok(lines)
+}
+
+/// Formats a single instruction as assembly
+pub(crate) fn format_instruction(
+    code: &[u8],
+    offset: usize,
+    symbols: &[(u32, String)],
+) -> Result<String, ParseError> {
+    let opcode = code[0];
+    let name = mnemonic(opcode).ok_or(ParseError::UnknownOpcode { offset, opcode })?;
+    let register = |register: u8| {
+        get_register_name_by_code(register).ok_or(ParseError::InvalidRegister {
+            offset,
+            opcode,
+            register,
+        })
+    };
+
+    let instruction = match opcode {
+        T_EXIT | T_CLEAR | T_PRINT => format!("{} {}", name, register(code[1])?),
+        T_SET => format!("{} 0x{:02X} {}", name, code[1], register(code[2])?),
+        T_SETW => format!(
+            "{} 0x{:02X} {}",
+            name,
+            u32::from_be_bytes([code[1], code[2], code[3], code[4]]),
+            register(code[5])?
+        ),
+        T_COPY => format!("{} {} {}", name, register(code[1])?, register(code[2])?),
+        T_LABEL => {
+            let id = u32::from_be_bytes([code[1], code[2], code[3], code[4]]);
+            if let Some((_, symbol)) = symbols.iter().find(|(label, _)| *label == id) {
+                format!("{} {}:", name, symbol)
+            } else {
+                format!("{} 0x{:02X}", name, id)
+            }
+        }
+        T_OVERFLOW => {
+            let mode = OverflowMode::from_code(code[1]).ok_or(ParseError::InvalidOperand {
+                offset,
+                opcode,
+                operand: code[1],
+            })?;
+            format!("{} {}", name, mode.name())
+        }
+        T_FADE => {
+            let easing = Easing::from_code(code[1] & !FADE_BACKGROUND).ok_or(
+                ParseError::InvalidOperand {
+                    offset,
+                    opcode,
+                    operand: code[1],
+                },
+            )?;
+            if code[1] & FADE_BACKGROUND != 0 {
+                format!("{} {} background", name, easing.name())
+            } else {
+                format!("{} {}", name, easing.name())
+            }
+        }
+        _ => name.to_string(),
+    };
+
+    Ok(instruction)
+}
@@ -0,0 +1,264 @@
+use crate::ledstrip_controller::PROGRAM_SPEEDS;
+use crate::registers::RegisterSnapshot;
+use crate::tokens::mnemonic;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// An error that occurred while parsing bytecode into tokens
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    TruncatedOperand {
+        offset: usize,
+        opcode: u8,
+    },
+    UnknownOpcode {
+        offset: usize,
+        opcode: u8,
+    },
+    DuplicateLabel {
+        offset: usize,
+        opcode: u8,
+        label: u32,
+    },
+    DuplicateOverflowMode {
+        offset: usize,
+        opcode: u8,
+    },
+    InvalidRegister {
+        offset: usize,
+        opcode: u8,
+        register: u8,
+    },
+    InvalidOperand {
+        offset: usize,
+        opcode: u8,
+        operand: u8,
+    },
+    MissingHeader,
+    MalformedContainer {
+        offset: usize,
+    },
+    ChecksumMismatch {
+        offset: usize,
+        expected: u32,
+        actual: u32,
+    },
+    UnsupportedVersion {
+        offset: usize,
+        version: u8,
+    },
+    IncompatibleVmVersion {
+        offset: usize,
+        required: u16,
+    },
+}
+
+impl ParseError {
+    /// Returns the byte offset of the instruction or
+    /// container field that caused the error
+    pub fn offset(&self) -> usize {
+        match self {
+            ParseError::TruncatedOperand { offset, .. }
+            | ParseError::UnknownOpcode { offset, .. }
+            | ParseError::DuplicateLabel { offset, .. }
+            | ParseError::DuplicateOverflowMode { offset, .. }
+            | ParseError::InvalidRegister { offset, .. }
+            | ParseError::InvalidOperand { offset, .. }
+            | ParseError::MalformedContainer { offset }
+            | ParseError::ChecksumMismatch { offset, .. }
+            | ParseError::UnsupportedVersion { offset, .. }
+            | ParseError::IncompatibleVmVersion { offset, .. } => *offset,
+            ParseError::MissingHeader => 0,
+        }
+    }
+
+    /// Returns the opcode of the instruction that caused the error
+    pub fn opcode(&self) -> Option<u8> {
+        match self {
+            ParseError::TruncatedOperand { opcode, .. }
+            | ParseError::UnknownOpcode { opcode, .. }
+            | ParseError::DuplicateLabel { opcode, .. }
+            | ParseError::DuplicateOverflowMode { opcode, .. }
+            | ParseError::InvalidRegister { opcode, .. }
+            | ParseError::InvalidOperand { opcode, .. } => Some(*opcode),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TruncatedOperand { offset, opcode } => write!(
+                f,
+                "truncated operand for instruction 0x{:02X} at offset 0x{:04X}",
+                opcode, offset
+            ),
+            ParseError::UnknownOpcode { offset, opcode } => {
+                write!(
+                    f,
+                    "unknown opcode 0x{:02X} at offset 0x{:04X}",
+                    opcode, offset
+                )
+            }
+            ParseError::DuplicateLabel {
+                offset,
+                opcode,
+                label,
+            } => write!(
+                f,
+                "duplicate label {} (instruction 0x{:02X}) at offset 0x{:04X}",
+                label, opcode, offset
+            ),
+            ParseError::DuplicateOverflowMode { offset, opcode } => write!(
+                f,
+                "the overflow mode is already set (instruction 0x{:02X}) at offset 0x{:04X}",
+                opcode, offset
+            ),
+            ParseError::InvalidRegister {
+                offset,
+                opcode,
+                register,
+            } => write!(
+                f,
+                "invalid register code 0x{:02X} for instruction 0x{:02X} at offset 0x{:04X}",
+                register, opcode, offset
+            ),
+            ParseError::InvalidOperand {
+                offset,
+                opcode,
+                operand,
+            } => write!(
+                f,
+                "invalid operand 0x{:02X} for instruction 0x{:02X} at offset 0x{:04X}",
+                operand, opcode, offset
+            ),
+            ParseError::MissingHeader => write!(
+                f,
+                "the file is not a bytecode container (legacy files have to be allowed explicitly)"
+            ),
+            ParseError::MalformedContainer { offset } => {
+                write!(f, "malformed container at offset 0x{:04X}", offset)
+            }
+            ParseError::ChecksumMismatch {
+                expected, actual, ..
+            } => write!(
+                f,
+                "checksum mismatch (expected 0x{:08X}, got 0x{:08X})",
+                expected, actual
+            ),
+            ParseError::UnsupportedVersion { version, .. } => {
+                write!(f, "unsupported container version {}", version)
+            }
+            ParseError::IncompatibleVmVersion { required, .. } => {
+                write!(f, "the program requires at least vm version {}", required)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// The cause of a fault during the execution of a program
+#[derive(Debug)]
+pub enum FaultKind {
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidRootDegree,
+    UnknownLabel(u32),
+    UnknownRegister(u8),
+    UnknownProgram(u32),
+    InvalidSpeed(u32),
+    UnknownStrip(u32),
+    PixelOutOfRange(u32),
+    Io(io::Error),
+}
+
+impl Clone for FaultKind {
+    /// Clones the kind. io errors keep their kind and message
+    fn clone(&self) -> Self {
+        match self {
+            FaultKind::ArithmeticOverflow => FaultKind::ArithmeticOverflow,
+            FaultKind::DivisionByZero => FaultKind::DivisionByZero,
+            FaultKind::InvalidRootDegree => FaultKind::InvalidRootDegree,
+            FaultKind::UnknownLabel(label) => FaultKind::UnknownLabel(*label),
+            FaultKind::UnknownRegister(register) => FaultKind::UnknownRegister(*register),
+            FaultKind::UnknownProgram(program) => FaultKind::UnknownProgram(*program),
+            FaultKind::InvalidSpeed(speed) => FaultKind::InvalidSpeed(*speed),
+            FaultKind::UnknownStrip(strip) => FaultKind::UnknownStrip(*strip),
+            FaultKind::PixelOutOfRange(pixel) => FaultKind::PixelOutOfRange(*pixel),
+            FaultKind::Io(e) => FaultKind::Io(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+impl Display for FaultKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultKind::ArithmeticOverflow => write!(f, "arithmetic overflow"),
+            FaultKind::DivisionByZero => write!(f, "division by zero"),
+            FaultKind::InvalidRootDegree => write!(f, "root degree must be at least 1"),
+            FaultKind::UnknownLabel(label) => write!(f, "the label {} does not exist", label),
+            FaultKind::UnknownRegister(register) => {
+                write!(f, "unknown register 0x{:02X}", register)
+            }
+            FaultKind::UnknownProgram(program) => write!(f, "unknown program 0x{:02X}", program),
+            FaultKind::InvalidSpeed(speed) => write!(
+                f,
+                "invalid program speed 0x{:02X} (expected 0x{:02X} to 0x{:02X})",
+                speed,
+                PROGRAM_SPEEDS.start(),
+                PROGRAM_SPEEDS.end()
+            ),
+            FaultKind::UnknownStrip(strip) => write!(f, "the strip {} does not exist", strip),
+            FaultKind::PixelOutOfRange(pixel) => {
+                write!(f, "the pixel {} is outside of the frame buffer", pixel)
+            }
+            FaultKind::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+/// A fault that stopped the execution of a program
+#[derive(Debug, Clone)]
+pub struct VmFault {
+    pub kind: FaultKind,
+    pub index: usize,
+    /// The source file and line of the instruction if the program has a source map
+    pub location: Option<String>,
+    pub opcode: u8,
+    pub registers: RegisterSnapshot,
+}
+
+pub type VmResult<T> = Result<T, VmFault>;
+
+impl Display for VmFault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "{} at {} {} (instruction {:0>4})\n{}",
+                self.kind,
+                location,
+                mnemonic(self.opcode).unwrap_or("?"),
+                self.index,
+                self.registers
+            ),
+            None => write!(
+                f,
+                "{} at instruction {:0>4} (opcode 0x{:02X})\n{}",
+                self.kind, self.index, self.opcode, self.registers
+            ),
+        }
+    }
+}
+
+impl Error for VmFault {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            FaultKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
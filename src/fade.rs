@@ -0,0 +1,236 @@
+use crate::clock::Clock;
+use crate::ledstrip_controller::Pixel;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The frame rate of fades if the frame rate isn't limited
+pub const DEFAULT_FADE_FPS: u32 = 50;
+
+/// The bit of the fade operand that runs the fade in the background
+pub const FADE_BACKGROUND: u8 = 0x80;
+
+const GAMMA: f64 = 2.2;
+
+/// How the color changes over the duration of a fade
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear = 0x01,
+    EaseInOut = 0x02,
+    /// Linear in perceived brightness instead of the raw values
+    Gamma = 0x03,
+}
+
+impl Easing {
+    /// Returns the easing for the bytecode value
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0x01 => Some(Easing::Linear),
+            0x02 => Some(Easing::EaseInOut),
+            0x03 => Some(Easing::Gamma),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the easing as used in the assembly
+    pub fn name(&self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::EaseInOut => "ease_in_out",
+            Easing::Gamma => "gamma",
+        }
+    }
+
+    /// Interpolates a single channel at the progress between 0 and 1
+    fn interpolate(&self, from: u8, to: u8, progress: f64) -> u8 {
+        let (from, to) = (from as f64 / 255.0, to as f64 / 255.0);
+        let value = match self {
+            Easing::Linear => from + (to - from) * progress,
+            Easing::EaseInOut => {
+                let progress = progress * progress * (3.0 - 2.0 * progress);
+                from + (to - from) * progress
+            }
+            Easing::Gamma => {
+                let (from, to) = (from.powf(GAMMA), to.powf(GAMMA));
+                (from + (to - from) * progress).powf(1.0 / GAMMA)
+            }
+        };
+
+        (value * 255.0).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+/// The names of the easings as used in the assembly
+pub const EASING_NAMES: [&str; 3] = ["linear", "ease_in_out", "gamma"];
+
+impl FromStr for Easing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Easing::Linear),
+            "ease_in_out" => Ok(Easing::EaseInOut),
+            "gamma" => Ok(Easing::Gamma),
+            _ => Err(format!("unknown easing {}", s)),
+        }
+    }
+}
+
+/// A running transition from one color to another
+#[derive(Debug, Clone)]
+pub struct Fade {
+    from: Pixel,
+    to: Pixel,
+    easing: Easing,
+    clock: Rc<dyn Clock>,
+    start: Duration,
+    duration: Duration,
+    interval: Duration,
+    next_frame: Duration,
+}
+
+impl Fade {
+    pub fn new(
+        from: Pixel,
+        to: Pixel,
+        duration: Duration,
+        easing: Easing,
+        fps: u32,
+        clock: Rc<dyn Clock>,
+    ) -> Self {
+        let start = clock.now();
+        let interval = Duration::from_secs(1) / fps.max(1);
+
+        // The strip already shows the start color, so the first frame is one interval in
+        Self {
+            from,
+            to,
+            easing,
+            clock,
+            start,
+            duration,
+            interval,
+            next_frame: start + interval.min(duration),
+        }
+    }
+
+    /// Returns the color at the given time since the start
+    pub fn color_at(&self, elapsed: Duration) -> Pixel {
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        let (from, to) = (self.from, self.to);
+
+        (
+            self.easing.interpolate(from.0, to.0, progress),
+            self.easing.interpolate(from.1, to.1, progress),
+            self.easing.interpolate(from.2, to.2, progress),
+        )
+    }
+
+    /// Returns if the last frame has been returned
+    pub fn is_finished(&self) -> bool {
+        self.next_frame > self.start + self.duration
+    }
+
+    /// Returns the time until the next frame is due
+    pub fn time_until_next_frame(&self) -> Duration {
+        self.next_frame.saturating_sub(self.clock.now())
+    }
+
+    /// Returns the color of the next frame if it's due
+    pub fn next_frame(&mut self) -> Option<Pixel> {
+        let now = self.clock.now();
+        if self.is_finished() || now < self.next_frame {
+            return None;
+        }
+        let elapsed = now - self.start;
+        self.next_frame = if elapsed >= self.duration {
+            self.start + self.duration + self.interval
+        } else {
+            (now + self.interval).min(self.start + self.duration)
+        };
+
+        Some(self.color_at(elapsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    const RED: Pixel = (255, 0, 0);
+    const BLUE: Pixel = (0, 0, 255);
+
+    fn red_to_blue(duration: u64) -> (Fade, Rc<VirtualClock>) {
+        let clock = Rc::new(VirtualClock::new());
+        let fade = Fade::new(
+            RED,
+            BLUE,
+            Duration::from_millis(duration),
+            Easing::Linear,
+            DEFAULT_FADE_FPS,
+            clock.clone(),
+        );
+
+        (fade, clock)
+    }
+
+    #[test]
+    fn easings_start_and_end_at_the_colors() {
+        for easing in [Easing::Linear, Easing::EaseInOut, Easing::Gamma].iter() {
+            assert_eq!(easing.interpolate(10, 200, 0.0), 10);
+            assert_eq!(easing.interpolate(10, 200, 1.0), 200);
+            assert_eq!(easing.interpolate(200, 10, 1.0), 10);
+        }
+    }
+
+    #[test]
+    fn easing_curves() {
+        let curve = |easing: Easing| -> Vec<u8> {
+            [0.25, 0.5, 0.75]
+                .iter()
+                .map(|progress| easing.interpolate(0, 255, *progress))
+                .collect()
+        };
+        assert_eq!(curve(Easing::Linear), [64, 128, 191]);
+        assert_eq!(curve(Easing::EaseInOut), [40, 128, 215]);
+        assert_eq!(curve(Easing::Gamma), [136, 186, 224]);
+        assert_eq!(Easing::Gamma.interpolate(255, 0, 0.5), 186);
+    }
+
+    #[test]
+    fn frames_follow_the_frame_rate() {
+        let (mut fade, clock) = red_to_blue(100);
+        assert_eq!(fade.next_frame(), None);
+        assert_eq!(fade.time_until_next_frame(), Duration::from_millis(20));
+
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(fade.next_frame(), Some((204, 0, 51)));
+        assert_eq!(fade.next_frame(), None);
+
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(fade.next_frame(), Some((128, 0, 128)));
+        assert_eq!(fade.time_until_next_frame(), Duration::from_millis(20));
+
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(fade.next_frame(), Some((26, 0, 229)));
+        assert_eq!(fade.time_until_next_frame(), Duration::from_millis(10));
+        assert!(!fade.is_finished());
+    }
+
+    #[test]
+    fn the_last_frame_is_the_end_color() {
+        let (mut fade, clock) = red_to_blue(100);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(fade.next_frame(), Some(BLUE));
+        assert!(fade.is_finished());
+        assert_eq!(fade.next_frame(), None);
+
+        let (mut fade, _) = red_to_blue(0);
+        assert_eq!(fade.next_frame(), Some(BLUE));
+        assert!(fade.is_finished());
+    }
+}
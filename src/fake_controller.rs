@@ -0,0 +1,249 @@
+use crate::ledstrip_controller::{
+    create_status_response, decode_message, frame_length, StripMessage, StripStatus, STATUS_QUERY,
+};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A message that was received by the fake controller
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedCommand {
+    /// The time since the controller was started
+    pub elapsed: Duration,
+    pub message: StripMessage,
+}
+
+/// Bytes that couldn't be decoded because of an unknown prefix,
+/// a missing terminator or a wrong checksum
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub elapsed: Duration,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    commands: Vec<ReceivedCommand>,
+    errors: Vec<ProtocolError>,
+    status: StripStatus,
+    queries: usize,
+}
+
+/// A local tcp server that speaks the protocol of the led strip controller
+/// and records every command it receives. The server stops when it's dropped
+#[derive(Debug)]
+pub struct FakeController {
+    address: SocketAddr,
+    log: Arc<Mutex<Log>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl FakeController {
+    /// Starts listening on the address. Use port 0 to get a free port
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let controller = Self {
+            address: listener.local_addr()?,
+            log: Arc::new(Mutex::new(Log::default())),
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+        let log = Arc::clone(&controller.log);
+        let stopped = Arc::clone(&controller.stopped);
+        let start = Instant::now();
+
+        thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let log = Arc::clone(&log);
+                        let stopped = Arc::clone(&stopped);
+                        thread::spawn(move || handle_connection(stream, start, log, stopped));
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL_INTERVAL)
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(controller)
+    }
+
+    /// Returns the address the controller is listening on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns all commands received so far in the order they arrived
+    pub fn commands(&self) -> Vec<ReceivedCommand> {
+        self.log.lock().unwrap().commands.clone()
+    }
+
+    /// Returns all received messages without the timestamps
+    pub fn messages(&self) -> Vec<StripMessage> {
+        self.commands()
+            .into_iter()
+            .map(|command| command.message)
+            .collect()
+    }
+
+    /// Returns the state the controller reports for status queries
+    pub fn status(&self) -> StripStatus {
+        self.log.lock().unwrap().status
+    }
+
+    /// Changes the state of the strip as if it was changed by another client
+    pub fn set_status(&self, status: StripStatus) {
+        self.log.lock().unwrap().status = status;
+    }
+
+    /// Returns the number of status queries that have been answered
+    pub fn queries(&self) -> usize {
+        self.log.lock().unwrap().queries
+    }
+
+    /// Returns all bytes that couldn't be decoded
+    pub fn errors(&self) -> Vec<ProtocolError> {
+        self.log.lock().unwrap().errors.clone()
+    }
+
+    /// Waits until at least the given number of commands has been received.
+    /// Returns false if the timeout was reached first
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.log.lock().unwrap().commands.len() < count {
+            if start.elapsed() > timeout {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        true
+    }
+}
+
+impl Drop for FakeController {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Reads messages from a connection until it's closed or the controller is stopped
+fn handle_connection(
+    mut stream: TcpStream,
+    start: Instant,
+    log: Arc<Mutex<Log>>,
+    stopped: Arc<AtomicBool>,
+) {
+    if stream.set_read_timeout(Some(POLL_INTERVAL * 10)).is_err() {
+        return;
+    }
+    let mut buffer = Vec::new();
+    let mut invalid = Vec::new();
+    let mut chunk = [0u8; 256];
+
+    while !stopped.load(Ordering::SeqCst) {
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(length) => {
+                buffer.extend_from_slice(&chunk[..length]);
+                let responses = decode_buffer(&mut buffer, &mut invalid, start, &log);
+                if responses
+                    .iter()
+                    .any(|response| stream.write_all(response).is_err())
+                {
+                    break;
+                }
+            }
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            }
+            Err(_) => break,
+        }
+    }
+    record_invalid(&mut invalid, start, &mut log.lock().unwrap());
+}
+
+/// Decodes all complete messages at the start of the buffer and returns the
+/// responses to status queries. Invalid bytes are moved into `invalid` until
+/// the next valid message starts, so a run of them is recorded as one error
+fn decode_buffer(
+    buffer: &mut Vec<u8>,
+    invalid: &mut Vec<u8>,
+    start: Instant,
+    log: &Mutex<Log>,
+) -> Vec<Vec<u8>> {
+    let mut log = log.lock().unwrap();
+    let mut responses = Vec::new();
+
+    while let Some(prefix) = buffer.first() {
+        if *prefix == STATUS_QUERY[0] {
+            if buffer.len() < STATUS_QUERY.len() {
+                break;
+            }
+            if buffer[..STATUS_QUERY.len()] == STATUS_QUERY {
+                record_invalid(invalid, start, &mut log);
+                responses.push(create_status_response(&log.status));
+                log.queries += 1;
+                buffer.drain(..STATUS_QUERY.len());
+            } else {
+                invalid.push(buffer.remove(0));
+            }
+            continue;
+        }
+        let length = match frame_length(buffer) {
+            Some(length) => length,
+            None => {
+                invalid.push(buffer.remove(0));
+                continue;
+            }
+        };
+        if buffer.len() < length {
+            break;
+        }
+        if let Some(message) = decode_message(&buffer[..length]) {
+            record_invalid(invalid, start, &mut log);
+            match message {
+                StripMessage::Color(r, g, b) => {
+                    log.status.r = r;
+                    log.status.g = g;
+                    log.status.b = b;
+                }
+                StripMessage::ColorWhite(r, g, b, w) => {
+                    log.status.r = r.saturating_add(w);
+                    log.status.g = g.saturating_add(w);
+                    log.status.b = b.saturating_add(w);
+                }
+                StripMessage::State(on) => log.status.on = on,
+                StripMessage::Program(..) => {}
+            }
+            log.commands.push(ReceivedCommand {
+                elapsed: start.elapsed(),
+                message,
+            });
+            buffer.drain(..length);
+        } else {
+            invalid.push(buffer.remove(0));
+        }
+    }
+
+    responses
+}
+
+/// Records the skipped bytes as a single error
+fn record_invalid(invalid: &mut Vec<u8>, start: Instant, log: &mut Log) {
+    if !invalid.is_empty() {
+        log.errors.push(ProtocolError {
+            elapsed: start.elapsed(),
+            bytes: std::mem::take(invalid),
+        });
+    }
+}
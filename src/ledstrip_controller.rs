@@ -1,15 +1,31 @@
+use crate::backends::{NullBackend, StripBackend};
+use crate::clock::{real_clock, Clock};
+use crate::fade::{Easing, Fade, DEFAULT_FADE_FPS};
+use crate::limiter::{FrameLimiter, LimiterOptions};
+use crate::protocols::{MagicHomeProtocol, Protocol};
 use std::io;
-use std::io::Write;
-use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::Duration;
 
-const STATE_COMMAND_PREFIX: u8 = 0x71;
-const PROGRAM_COMMAND_PREFIX: u8 = 0x61;
+pub(crate) const COLOR_COMMAND_PREFIX: u8 = 0x31;
+pub(crate) const STATE_COMMAND_PREFIX: u8 = 0x71;
+pub(crate) const PROGRAM_COMMAND_PREFIX: u8 = 0x61;
+const MESSAGE_TERMINATOR: u8 = 0x0f;
+const STATUS_QUERY_PREFIX: u8 = 0x81;
+const STATUS_RESPONSE_PREFIX: u8 = 0x81;
+
+/// The message that asks the controller for its current state
+pub const STATUS_QUERY: [u8; 4] = [STATUS_QUERY_PREFIX, 0x8a, 0x8b, 0x96];
+
+/// The length of the response to a status query
+pub const STATUS_RESPONSE_LENGTH: usize = 14;
 
 pub enum StateStripCommand {
     On = 0x23,
     Off = 0x24,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ProgramStripCommand {
     SevenCrossFade = 0x25,
     RedGradual = 0x26,
@@ -27,76 +43,420 @@ pub enum ProgramStripCommand {
     SevenJumping = 0x38,
 }
 
+/// The names of the built-in programs as used in the assembly
+pub const PROGRAM_NAMES: [(&str, ProgramStripCommand); 14] = [
+    ("seven_cross_fade", ProgramStripCommand::SevenCrossFade),
+    ("red_gradual", ProgramStripCommand::RedGradual),
+    ("green_gradual", ProgramStripCommand::GreenGradual),
+    ("blue_gradual", ProgramStripCommand::BlueGradual),
+    ("white_gradual", ProgramStripCommand::WhiteGradual),
+    ("red_green_cross", ProgramStripCommand::RedGreenCross),
+    ("red_blue_cross", ProgramStripCommand::RedBlueCross),
+    ("green_blue_cross", ProgramStripCommand::GreenBlueCross),
+    ("seven_strobe", ProgramStripCommand::SevenStrobe),
+    ("red_strobe", ProgramStripCommand::RedStrobe),
+    ("green_strobe", ProgramStripCommand::GreenStrobe),
+    ("blue_strobe", ProgramStripCommand::BlueStrobe),
+    ("white_strobe", ProgramStripCommand::WhiteStrobe),
+    ("seven_jumping", ProgramStripCommand::SevenJumping),
+];
+
+/// The range of valid program speeds from fastest to slowest
+pub const PROGRAM_SPEEDS: std::ops::RangeInclusive<u8> = 0x01..=0x1F;
+
+impl ProgramStripCommand {
+    /// Returns the program for the bytecode value
+    pub fn from_code(code: u8) -> Option<Self> {
+        PROGRAM_NAMES
+            .iter()
+            .find(|(_, program)| *program as u8 == code)
+            .map(|(_, program)| *program)
+    }
+
+    /// Returns the assembly name of the program
+    pub fn name(&self) -> &'static str {
+        PROGRAM_NAMES
+            .iter()
+            .find(|(_, program)| program == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+
+    /// Returns the program with the given assembly name
+    pub fn from_name(name: &str) -> Option<Self> {
+        PROGRAM_NAMES
+            .iter()
+            .find(|(program_name, _)| *program_name == name)
+            .map(|(_, program)| *program)
+    }
+}
+
+/// The rgb color of a single pixel of an addressable strip
+pub type Pixel = (u8, u8, u8);
+
 #[derive(Debug)]
 pub struct LedStripController {
-    stream: Option<TcpStream>,
+    backend: Box<dyn StripBackend>,
+    protocol: Box<dyn Protocol>,
+    clock: Rc<dyn Clock>,
+    pub limiter: FrameLimiter,
+    /// The fade that is currently running
+    pub fade: Option<Fade>,
     pub r: u8,
     pub g: u8,
     pub b: u8,
+    /// The frame buffer for addressable strips
+    pub pixels: Vec<Pixel>,
 }
 
 impl LedStripController {
-    pub fn new(ip: &str, port: usize) -> io::Result<Self> {
-        let stream = if let Ok(tcp_stream) = TcpStream::connect(format!("{}:{}", ip, port)) {
-            Some(tcp_stream)
-        } else {
-            eprintln!("Failed to connect. Writing sent data to stdout.");
-            None
-        };
+    pub fn new(backend: Box<dyn StripBackend>) -> Self {
+        Self::with_protocol(backend, Box::new(MagicHomeProtocol))
+    }
 
-        Ok(Self {
-            stream,
+    /// Creates a controller that encodes the messages with the given protocol
+    pub fn with_protocol(backend: Box<dyn StripBackend>, protocol: Box<dyn Protocol>) -> Self {
+        Self {
+            backend,
+            protocol,
+            clock: real_clock(),
+            limiter: FrameLimiter::default(),
+            fade: None,
             r: 0,
             g: 0,
             b: 0,
-        })
+            pixels: Vec::new(),
+        }
+    }
+
+    /// Replaces the backend with one that wraps the current backend
+    pub fn wrap_backend(
+        &mut self,
+        wrap: impl FnOnce(Box<dyn StripBackend>) -> Box<dyn StripBackend>,
+    ) {
+        let backend = std::mem::replace(&mut self.backend, Box::new(NullBackend));
+        self.backend = wrap(backend);
     }
 
+    /// Sends the message. The limiter forgets the shown color
+    /// if the connection dropped since the strip may have been changed meanwhile
     fn send(&mut self, message: &[u8]) -> io::Result<()> {
-        if let Some(stream) = &mut self.stream {
-            stream.write(message)?;
-            Ok(())
-        } else {
-            println!("Send: {:?}", message);
-            Ok(())
+        let result = self.backend.send(message);
+        if self.backend.take_disconnected() {
+            self.limiter.forget();
         }
+
+        result
+    }
+
+    /// Sets the clock of the limiter, the fades and the backend
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.limiter.set_clock(clock.clone());
+        self.backend.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /// Replaces the frame limiter for the colors
+    pub fn set_limiter(&mut self, options: LimiterOptions) {
+        self.limiter = FrameLimiter::with_clock(options, self.clock.clone());
     }
 
-    /// Send an rgb color to the led strip
+    /// Send an rgb color to the led strip. Depending on the limiter
+    /// the color is sent later or not at all. A running fade is stopped
     pub fn send_rgb_color(&mut self, r: u8, g: u8, b: u8) -> io::Result<()> {
+        self.fade = None;
+        self.send_color(r, g, b)
+    }
+
+    fn send_color(&mut self, r: u8, g: u8, b: u8) -> io::Result<()> {
         self.r = r;
         self.g = g;
         self.b = b;
-        let message = create_message(&[0x31, r, g, b, 0xf0]);
-        self.send(&message)?;
+        if let Some((r, g, b)) = self.limiter.admit((r, g, b)) {
+            let message = self.protocol.color(r, g, b);
+            self.send(&message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts a fade from the last color to the given one. The frames
+    /// are sent with the limited frame rate by calling advance_fade
+    pub fn start_fade(&mut self, to: Pixel, duration: Duration, easing: Easing) {
+        let fps = match self.limiter.max_fps() {
+            0 => DEFAULT_FADE_FPS,
+            fps => fps,
+        };
+        self.fade = Some(Fade::new(
+            (self.r, self.g, self.b),
+            to,
+            duration,
+            easing,
+            fps,
+            self.clock.clone(),
+        ));
+    }
+
+    /// Sends the next frame of the running fade if it's due.
+    /// Returns the time until the next frame or None if no fade is running
+    pub fn advance_fade(&mut self) -> io::Result<Option<Duration>> {
+        let color = match self.fade.as_mut() {
+            Some(fade) => fade.next_frame(),
+            None => return Ok(None),
+        };
+        if let Some((r, g, b)) = color {
+            self.send_color(r, g, b)?;
+        }
+        match &self.fade {
+            Some(fade) if !fade.is_finished() => Ok(Some(fade.time_until_next_frame())),
+            _ => {
+                self.fade = None;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sends the color that was held back by the limiter
+    /// if it's due or if forced
+    pub fn flush(&mut self, force: bool) -> io::Result<()> {
+        if let Some((r, g, b)) = self.limiter.flush(force) {
+            let message = self.protocol.color(r, g, b);
+            self.send(&message)?;
+        }
 
         Ok(())
     }
 
     /// Sets the state of the strip to either on or off
     pub fn set_state(&mut self, cmd: StateStripCommand) -> io::Result<()> {
-        self.send(&create_message(&[STATE_COMMAND_PREFIX, cmd as u8]))?;
+        self.flush(true)?;
+        // the strip may not show the last color after it was turned off and on
+        self.limiter.forget();
+        let message = self.protocol.state(cmd);
+        self.send(&message)?;
 
         Ok(())
     }
     /// Sends a strip command with a specified speed
-    /// that is one of 0x01 0x06, 0x10, 0x1c
+    /// between 0x01 (fast) and 0x1F (slow)
     pub fn send_command(&mut self, cmd: ProgramStripCommand, speed: u8) -> io::Result<()> {
-        self.send(&create_message(&[PROGRAM_COMMAND_PREFIX, cmd as u8, speed]))?;
+        self.fade = None;
+        self.flush(true)?;
+        // the program changes the color so the next one has to be sent
+        self.limiter.forget();
+        let message = self.protocol.program(cmd, speed);
+        self.send(&message)?;
 
         Ok(())
     }
+
+    /// Sends the content of the frame buffer to the strip
+    pub fn send_frame(&mut self) -> io::Result<()> {
+        self.backend.send_frame(&self.pixels)
+    }
+
+    /// Queries the actual state and color of the strip and updates
+    /// the cached color with it
+    pub fn query_status(&mut self) -> io::Result<StripStatus> {
+        self.flush(true)?;
+        self.send(&STATUS_QUERY)?;
+        let mut response = [0u8; STATUS_RESPONSE_LENGTH];
+        let received = self.backend.receive(&mut response);
+        if self.backend.take_disconnected() {
+            self.limiter.forget();
+        }
+        received?;
+        let status = decode_status(&response)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid status response"))?;
+        self.r = status.r;
+        self.g = status.g;
+        self.b = status.b;
+        if status.on {
+            self.limiter.set_shown((status.r, status.g, status.b));
+        } else {
+            self.limiter.forget();
+        }
+
+        Ok(status)
+    }
 }
 
-/// Create a message for the led strip
-fn create_message(data: &[u8]) -> Vec<u8> {
-    let mut data = data.clone().to_vec();
-    data.append(&mut vec![0x0f]);
-    let mut sum = 0u128;
-    for i in &data {
-        sum += *i as u128;
+/// The state of the strip as reported by the controller
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StripStatus {
+    pub on: bool,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Decodes the response to a status query checking the checksum
+pub fn decode_status(response: &[u8]) -> Option<StripStatus> {
+    if response.len() != STATUS_RESPONSE_LENGTH
+        || response[0] != STATUS_RESPONSE_PREFIX
+        || checksum(&response[..STATUS_RESPONSE_LENGTH - 1]) != response[STATUS_RESPONSE_LENGTH - 1]
+    {
+        return None;
+    }
+
+    Some(StripStatus {
+        on: response[2] == StateStripCommand::On as u8,
+        r: response[6],
+        g: response[7],
+        b: response[8],
+    })
+}
+
+/// Creates the response the controller sends for a status query
+pub fn create_status_response(status: &StripStatus) -> Vec<u8> {
+    let state = if status.on {
+        StateStripCommand::On
+    } else {
+        StateStripCommand::Off
+    };
+    let mut response = vec![
+        STATUS_RESPONSE_PREFIX,
+        0x04,
+        state as u8,
+        0x61,
+        0x00,
+        0x01,
+        status.r,
+        status.g,
+        status.b,
+        0x00,
+        0x00,
+        0x00,
+        0x0f,
+    ];
+    response.push(checksum(&response));
+
+    response
+}
+
+/// A decoded message that was sent to the strip
+#[derive(Debug, Clone, PartialEq)]
+pub enum StripMessage {
+    Color(u8, u8, u8),
+    /// A color with a value for the warm white channel
+    ColorWhite(u8, u8, u8, u8),
+    State(bool),
+    Program(ProgramStripCommand, u8),
+}
+
+const RGBW_COLOR_LENGTH: usize = 8;
+
+/// Returns the length of the message that starts with the given prefix
+pub fn message_length(prefix: u8) -> Option<usize> {
+    match prefix {
+        COLOR_COMMAND_PREFIX => Some(7),
+        PROGRAM_COMMAND_PREFIX => Some(5),
+        STATE_COMMAND_PREFIX => Some(4),
+        _ => None,
+    }
+}
+
+/// Returns the length of the message at the start of the buffer. Color messages
+/// with a white channel are detected by the missing terminator at the usual position
+pub fn frame_length(buffer: &[u8]) -> Option<usize> {
+    let prefix = *buffer.first()?;
+    let length = message_length(prefix)?;
+    match buffer.get(length - 2) {
+        Some(byte) if prefix == COLOR_COMMAND_PREFIX && *byte != MESSAGE_TERMINATOR => {
+            Some(RGBW_COLOR_LENGTH)
+        }
+        _ => Some(length),
     }
-    data.push(sum as u8 & 255);
+}
+
+/// Decodes a complete message checking the terminator and the checksum.
+/// Color messages with a white channel are one byte longer
+pub fn decode_message(message: &[u8]) -> Option<StripMessage> {
+    let prefix = *message.first()?;
+    let is_rgbw = prefix == COLOR_COMMAND_PREFIX && message.len() == RGBW_COLOR_LENGTH;
+    if (message.len() != message_length(prefix)? && !is_rgbw)
+        || message[message.len() - 2] != MESSAGE_TERMINATOR
+        || create_message(&message[..message.len() - 2]) != message
+    {
+        return None;
+    }
+
+    match message {
+        [COLOR_COMMAND_PREFIX, r, g, b, w, ..] if is_rgbw => {
+            Some(StripMessage::ColorWhite(*r, *g, *b, *w))
+        }
+        [COLOR_COMMAND_PREFIX, r, g, b, ..] => Some(StripMessage::Color(*r, *g, *b)),
+        [STATE_COMMAND_PREFIX, state, ..] if *state == StateStripCommand::On as u8 => {
+            Some(StripMessage::State(true))
+        }
+        [STATE_COMMAND_PREFIX, state, ..] if *state == StateStripCommand::Off as u8 => {
+            Some(StripMessage::State(false))
+        }
+        [PROGRAM_COMMAND_PREFIX, program, speed, ..] => Some(StripMessage::Program(
+            ProgramStripCommand::from_code(*program)?,
+            *speed,
+        )),
+        _ => None,
+    }
+}
+
+/// Create a message for the led strip
+pub(crate) fn create_message(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data.append(&mut vec![MESSAGE_TERMINATOR]);
+    data.push(checksum(&data));
 
     data
 }
+
+/// Returns the sum of all bytes truncated to a byte
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::RecordingBackend;
+
+    /// Creates a controller that skips colors the strip already shows
+    fn coalescing_controller(recording: &RecordingBackend) -> LedStripController {
+        let mut controller = LedStripController::new(Box::new(recording.clone()));
+        controller.set_limiter(LimiterOptions {
+            coalesce: true,
+            ..LimiterOptions::default()
+        });
+
+        controller
+    }
+
+    #[test]
+    fn colors_are_sent_again_after_a_program() {
+        let recording = RecordingBackend::new();
+        let mut controller = coalescing_controller(&recording);
+        controller.send_rgb_color(255, 0, 0).unwrap();
+        controller.send_rgb_color(255, 0, 0).unwrap();
+        controller
+            .send_command(ProgramStripCommand::SevenCrossFade, 0x05)
+            .unwrap();
+        controller.send_rgb_color(255, 0, 0).unwrap();
+
+        let red = create_message(&[0x31, 0xff, 0x00, 0x00, 0xf0]);
+        let program = create_message(&[0x61, 0x25, 0x05]);
+        assert_eq!(recording.frames(), vec![red.clone(), program, red]);
+        assert_eq!(controller.limiter.stats.merged, 1);
+    }
+
+    #[test]
+    fn colors_are_sent_again_after_a_state_change() {
+        let recording = RecordingBackend::new();
+        let mut controller = coalescing_controller(&recording);
+        controller.send_rgb_color(0, 0, 255).unwrap();
+        controller.set_state(StateStripCommand::Off).unwrap();
+        controller.set_state(StateStripCommand::On).unwrap();
+        controller.send_rgb_color(0, 0, 255).unwrap();
+
+        assert_eq!(recording.frames().len(), 4);
+        assert_eq!(controller.limiter.stats.sent, 2);
+    }
+}
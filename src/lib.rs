@@ -1,4 +1,18 @@
+pub mod assembler;
+pub mod backends;
+pub mod clock;
+pub mod color;
+pub mod container;
+pub mod debugger;
+pub mod disassembler;
+pub mod errors;
+pub mod fade;
+pub mod fake_controller;
 pub mod ledstrip_controller;
+pub mod limiter;
+pub mod profiler;
+pub mod protocols;
 pub mod registers;
 pub mod runtime;
 pub mod tokens;
+pub mod trace;
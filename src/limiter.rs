@@ -0,0 +1,290 @@
+use crate::clock::{real_clock, Clock};
+use crate::ledstrip_controller::Pixel;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What happens to a color that is sent before the next frame is due
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitMode {
+    /// Waits until the frame is due
+    Block,
+    /// Keeps only the latest color and sends it once the frame is due
+    Latest,
+}
+
+impl FromStr for LimitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(LimitMode::Block),
+            "latest" => Ok(LimitMode::Latest),
+            _ => Err(format!("unknown frame mode {}", s)),
+        }
+    }
+}
+
+/// The settings of the frame limiter
+#[derive(Debug, Clone)]
+pub struct LimiterOptions {
+    /// The maximum number of colors sent per second. 0 means unlimited
+    pub max_fps: u32,
+    pub mode: LimitMode,
+    /// Skips colors that are the same as the last one that was sent
+    pub coalesce: bool,
+}
+
+impl Default for LimiterOptions {
+    fn default() -> Self {
+        Self {
+            max_fps: 0,
+            mode: LimitMode::Block,
+            coalesce: false,
+        }
+    }
+}
+
+/// Counts what happened to the colors that were sent to a strip
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    /// Colors that were sent to the strip
+    pub sent: u64,
+    /// Colors that were skipped because the strip already shows them
+    pub merged: u64,
+    /// Colors that were replaced by a newer one before they were due
+    pub dropped: u64,
+    /// Colors that had to wait for the frame to be due
+    pub delayed: u64,
+}
+
+impl Display for FrameStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {}, merged {}, dropped {}, delayed {}",
+            self.sent, self.merged, self.dropped, self.delayed
+        )
+    }
+}
+
+/// Limits the rate of the colors that are sent to a strip
+#[derive(Debug, Clone)]
+pub struct FrameLimiter {
+    options: LimiterOptions,
+    clock: Rc<dyn Clock>,
+    last_frame: Option<Duration>,
+    last_color: Option<Pixel>,
+    pending: Option<Pixel>,
+    pub stats: FrameStats,
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self::new(LimiterOptions::default())
+    }
+}
+
+impl FrameLimiter {
+    pub fn new(options: LimiterOptions) -> Self {
+        Self::with_clock(options, real_clock())
+    }
+
+    /// Creates a limiter that measures the time between frames with the clock
+    pub fn with_clock(options: LimiterOptions, clock: Rc<dyn Clock>) -> Self {
+        Self {
+            options,
+            clock,
+            last_frame: None,
+            last_color: None,
+            pending: None,
+            stats: FrameStats::default(),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Returns the maximum number of colors per second or 0 if it's unlimited
+    pub fn max_fps(&self) -> u32 {
+        self.options.max_fps
+    }
+
+    /// Returns the time until the next frame is due
+    fn remaining(&self) -> Duration {
+        match (self.options.max_fps, self.last_frame) {
+            (0, _) | (_, None) => Duration::from_secs(0),
+            (fps, Some(last_frame)) => {
+                let interval = Duration::from_secs(1) / fps;
+                interval.saturating_sub(self.clock.now().saturating_sub(last_frame))
+            }
+        }
+    }
+
+    /// Returns the color if it should be sent now
+    pub fn admit(&mut self, color: Pixel) -> Option<Pixel> {
+        if self.options.coalesce && self.pending.is_none() && self.last_color == Some(color) {
+            self.stats.merged += 1;
+            return None;
+        }
+        let remaining = self.remaining();
+        if remaining > Duration::from_secs(0) {
+            match self.options.mode {
+                LimitMode::Block => {
+                    self.stats.delayed += 1;
+                    self.clock.sleep(remaining);
+                }
+                LimitMode::Latest => {
+                    if self.pending.replace(color).is_some() {
+                        self.stats.dropped += 1;
+                    }
+                    return None;
+                }
+            }
+        }
+        if self.pending.take().is_some() {
+            self.stats.dropped += 1;
+        }
+
+        Some(self.sent(color))
+    }
+
+    /// Returns the pending color if it's due or if forced
+    pub fn flush(&mut self, force: bool) -> Option<Pixel> {
+        if !force && self.remaining() > Duration::from_secs(0) {
+            return None;
+        }
+        let color = self.pending.take()?;
+        if self.options.coalesce && self.last_color == Some(color) {
+            self.stats.merged += 1;
+            return None;
+        }
+
+        Some(self.sent(color))
+    }
+
+    /// Records the color the strip shows without it being sent
+    pub fn set_shown(&mut self, color: Pixel) {
+        self.last_color = Some(color);
+    }
+
+    /// Forgets the color the strip shows when it's unknown, so the next color
+    /// is sent even if it's the same as the last one
+    pub fn forget(&mut self) {
+        self.last_color = None;
+    }
+
+    /// Records that the color was sent to the strip
+    fn sent(&mut self, color: Pixel) -> Pixel {
+        self.last_frame = Some(self.clock.now());
+        self.last_color = Some(color);
+        self.stats.sent += 1;
+
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    const RED: Pixel = (255, 0, 0);
+    const GREEN: Pixel = (0, 255, 0);
+    const BLUE: Pixel = (0, 0, 255);
+
+    fn limiter(max_fps: u32, mode: LimitMode) -> (FrameLimiter, Rc<VirtualClock>) {
+        let clock = Rc::new(VirtualClock::new());
+        let options = LimiterOptions {
+            max_fps,
+            mode,
+            coalesce: true,
+        };
+
+        (FrameLimiter::with_clock(options, clock.clone()), clock)
+    }
+
+    fn stats(sent: u64, merged: u64, dropped: u64, delayed: u64) -> FrameStats {
+        FrameStats {
+            sent,
+            merged,
+            dropped,
+            delayed,
+        }
+    }
+
+    #[test]
+    fn block_waits_for_the_next_frame() {
+        let (mut limiter, clock) = limiter(10, LimitMode::Block);
+        assert_eq!(limiter.admit(RED), Some(RED));
+        assert_eq!(limiter.admit(RED), None);
+        assert_eq!(limiter.admit(GREEN), Some(GREEN));
+        assert_eq!(clock.now(), Duration::from_millis(100));
+
+        clock.advance(Duration::from_millis(150));
+        assert_eq!(limiter.admit(BLUE), Some(BLUE));
+        assert_eq!(clock.now(), Duration::from_millis(250));
+        assert_eq!(limiter.stats, stats(3, 1, 0, 1));
+    }
+
+    #[test]
+    fn latest_keeps_only_the_newest_color() {
+        let (mut limiter, clock) = limiter(10, LimitMode::Latest);
+        assert_eq!(limiter.admit(RED), Some(RED));
+        assert_eq!(limiter.admit(GREEN), None);
+        assert_eq!(limiter.admit(BLUE), None);
+        assert_eq!(limiter.flush(false), None);
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(limiter.flush(false), Some(BLUE));
+        assert_eq!(limiter.admit(BLUE), None);
+        assert_eq!(limiter.admit(RED), None);
+        assert_eq!(limiter.flush(true), Some(RED));
+        assert_eq!(clock.now(), Duration::from_millis(100));
+        assert_eq!(limiter.stats, stats(3, 1, 1, 0));
+    }
+
+    #[test]
+    fn latest_merges_a_pending_color_that_is_shown() {
+        let (mut limiter, clock) = limiter(10, LimitMode::Latest);
+        assert_eq!(limiter.admit(RED), Some(RED));
+        assert_eq!(limiter.admit(GREEN), None);
+        assert_eq!(limiter.admit(RED), None);
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(limiter.flush(false), None);
+        assert_eq!(limiter.stats, stats(1, 1, 1, 0));
+    }
+
+    #[test]
+    fn unlimited_frames_are_never_delayed() {
+        let (mut limiter, clock) = limiter(0, LimitMode::Block);
+        for color in [RED, GREEN, BLUE, RED].iter() {
+            assert_eq!(limiter.admit(*color), Some(*color));
+        }
+        assert_eq!(clock.now(), Duration::from_secs(0));
+        assert_eq!(limiter.stats, stats(4, 0, 0, 0));
+    }
+
+    #[test]
+    fn colors_are_repeated_without_coalescing() {
+        let mut limiter =
+            FrameLimiter::with_clock(LimiterOptions::default(), Rc::new(VirtualClock::new()));
+        assert_eq!(limiter.admit(RED), Some(RED));
+        assert_eq!(limiter.admit(RED), Some(RED));
+        assert_eq!(limiter.stats, stats(2, 0, 0, 0));
+    }
+
+    #[test]
+    fn forgotten_colors_are_sent_again() {
+        let (mut limiter, _) = limiter(0, LimitMode::Block);
+        assert_eq!(limiter.admit(RED), Some(RED));
+        limiter.forget();
+        assert_eq!(limiter.admit(RED), Some(RED));
+        limiter.set_shown(GREEN);
+        assert_eq!(limiter.admit(GREEN), None);
+        assert_eq!(limiter.stats, stats(2, 1, 0, 0));
+    }
+}
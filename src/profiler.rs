@@ -0,0 +1,317 @@
+use std::cmp::Reverse;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+/// The number of instructions listed in the report
+const HOT_INSTRUCTIONS: usize = 20;
+
+/// The executions of a single instruction or a block
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counter {
+    pub count: u64,
+    /// The time spent executing excluding the time spent waiting
+    pub compute: Duration,
+    /// The time spent waiting in pause and fades
+    pub paused: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, other: &Counter) {
+        self.count += other.count;
+        self.compute += other.compute;
+        self.paused += other.paused;
+    }
+
+    fn total(&self) -> Duration {
+        self.compute + self.paused
+    }
+}
+
+/// Collects the executions of every instruction of a program
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    counters: Vec<Counter>,
+}
+
+impl Profile {
+    pub fn new(instruction_count: usize) -> Self {
+        Self {
+            counters: vec![Counter::default(); instruction_count],
+        }
+    }
+
+    /// Records an execution of the instruction at the index
+    pub fn record(&mut self, index: usize, compute: Duration, paused: Duration) {
+        if let Some(counter) = self.counters.get_mut(index) {
+            counter.count += 1;
+            counter.compute += compute;
+            counter.paused += paused;
+        }
+    }
+
+    /// Creates the report for the instructions with the given names and locations
+    /// and the blocks starting at the given indices
+    pub fn report(
+        &self,
+        instructions: Vec<(String, Option<String>)>,
+        blocks: Vec<(usize, String)>,
+    ) -> ProfileReport {
+        let instructions: Vec<InstructionProfile> = instructions
+            .into_iter()
+            .zip(&self.counters)
+            .enumerate()
+            .map(|(index, ((name, location), counter))| InstructionProfile {
+                index,
+                name,
+                location,
+                counter: *counter,
+            })
+            .collect();
+
+        let mut starts = blocks;
+        starts.sort_by_key(|(start, _)| *start);
+        if starts.first().map(|(start, _)| *start) != Some(0) {
+            starts.insert(0, (0, "(start)".to_string()));
+        }
+        let mut blocks: Vec<BlockProfile> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, (start, name))| {
+                let end = starts
+                    .get(i + 1)
+                    .map_or(instructions.len(), |(end, _)| *end)
+                    .max(*start);
+                let mut counter = Counter::default();
+                for instruction in instructions.iter().take(end).skip(*start) {
+                    counter.add(&instruction.counter);
+                }
+                BlockProfile {
+                    name: name.clone(),
+                    start: *start,
+                    end,
+                    counter,
+                }
+            })
+            .collect();
+        blocks.sort_by_key(|block| Reverse(block.counter.total()));
+
+        ProfileReport {
+            instructions,
+            blocks,
+        }
+    }
+}
+
+/// The executions of an instruction with its name and source location
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionProfile {
+    pub index: usize,
+    pub name: String,
+    pub location: Option<String>,
+    pub counter: Counter,
+}
+
+/// The executions of the instructions from a label up to the next one
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockProfile {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    pub counter: Counter,
+}
+
+/// The profile of a program with the blocks sorted by their time
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    pub instructions: Vec<InstructionProfile>,
+    pub blocks: Vec<BlockProfile>,
+}
+
+impl ProfileReport {
+    /// Formats the profile as folded stacks (`program;block;instruction microseconds`)
+    /// that can be turned into a flamegraph. Time spent waiting is
+    /// added as a pause frame below the instruction
+    pub fn folded(&self) -> String {
+        let mut lines = Vec::new();
+        for block in &self.blocks {
+            // blocks of labels past the last instruction are empty
+            let end = block.end.min(self.instructions.len());
+            for instruction in &self.instructions[block.start.min(end)..end] {
+                let stack = format!(
+                    "program;{};{:0>4} {}",
+                    block.name, instruction.index, instruction.name
+                );
+                let compute = instruction.counter.compute.as_micros();
+                let paused = instruction.counter.paused.as_micros();
+                if compute > 0 {
+                    lines.push(format!("{} {}", stack, compute));
+                }
+                if paused > 0 {
+                    lines.push(format!("{};pause {}", stack, paused));
+                }
+            }
+        }
+        lines.push(String::new());
+
+        lines.join("\n")
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut total = Counter::default();
+        for block in &self.blocks {
+            total.add(&block.counter);
+        }
+        writeln!(
+            f,
+            "{} instructions, compute {:?}, pause {:?}\n",
+            total.count, total.compute, total.paused
+        )?;
+
+        writeln!(
+            f,
+            "{:<24} {:>12} {:>14} {:>14}",
+            "block", "executions", "compute", "pause"
+        )?;
+        for block in &self.blocks {
+            writeln!(
+                f,
+                "{:<24} {:>12} {:>14} {:>14}",
+                block.name,
+                block.counter.count,
+                format!("{:?}", block.counter.compute),
+                format!("{:?}", block.counter.paused)
+            )?;
+        }
+
+        let mut instructions: Vec<&InstructionProfile> = self
+            .instructions
+            .iter()
+            .filter(|instruction| instruction.counter.count > 0)
+            .collect();
+        instructions.sort_by_key(|instruction| Reverse(instruction.counter.total()));
+        writeln!(
+            f,
+            "\n{:<6} {:<24} {:<8} {:>12} {:>14} {:>14}",
+            "index", "location", "name", "executions", "compute", "pause"
+        )?;
+        for instruction in instructions.iter().take(HOT_INSTRUCTIONS) {
+            writeln!(
+                f,
+                "{:0>4}   {:<24} {:<8} {:>12} {:>14} {:>14}",
+                instruction.index,
+                instruction.location.as_deref().unwrap_or("-"),
+                instruction.name,
+                instruction.counter.count,
+                format!("{:?}", instruction.counter.compute),
+                format!("{:?}", instruction.counter.paused)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+
+    fn names(count: usize) -> Vec<(String, Option<String>)> {
+        (0..count)
+            .map(|index| (format!("op{}", index), None))
+            .collect()
+    }
+
+    /// Six instructions where the instruction at index i took i milliseconds
+    /// and the one at index 3 paused for 10 milliseconds
+    fn profile() -> Profile {
+        let mut profile = Profile::new(6);
+        for index in 0..6 {
+            profile.record(index as usize, millis(index), Duration::default());
+        }
+        profile.record(3, Duration::default(), millis(10));
+        profile.record(9, millis(100), Duration::default());
+
+        profile
+    }
+
+    fn block_summary(report: &ProfileReport) -> Vec<(&str, usize, usize, u64, Duration)> {
+        report
+            .blocks
+            .iter()
+            .map(|block| {
+                (
+                    block.name.as_str(),
+                    block.start,
+                    block.end,
+                    block.counter.count,
+                    block.counter.total(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blocks_are_split_at_labels_and_sorted_by_time() {
+        let report = profile().report(
+            names(6),
+            vec![(4, "end".to_string()), (2, "loop".to_string())],
+        );
+        assert_eq!(
+            block_summary(&report),
+            vec![
+                ("loop", 2, 4, 3, millis(15)),
+                ("end", 4, 6, 2, millis(9)),
+                ("(start)", 0, 2, 2, millis(1)),
+            ]
+        );
+        assert_eq!(report.instructions[3].counter.count, 2);
+    }
+
+    #[test]
+    fn the_start_block_is_only_added_before_the_first_label() {
+        let report = profile().report(names(6), vec![(0, "main".to_string())]);
+        assert_eq!(block_summary(&report), vec![("main", 0, 6, 7, millis(25))]);
+
+        let report = profile().report(names(6), Vec::new());
+        assert_eq!(
+            block_summary(&report),
+            vec![("(start)", 0, 6, 7, millis(25))]
+        );
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let report = profile().report(names(4), vec![(2, "loop".to_string())]);
+        assert_eq!(
+            report.folded(),
+            "\
+program;loop;0002 op2 2000
+program;loop;0003 op3 3000
+program;loop;0003 op3;pause 10000
+program;(start);0001 op1 1000
+"
+        );
+    }
+
+    #[test]
+    fn blocks_past_the_last_instruction_are_empty() {
+        let report = profile().report(names(3), vec![(5, "late".to_string())]);
+        assert_eq!(
+            block_summary(&report),
+            vec![
+                ("(start)", 0, 5, 3, millis(3)),
+                ("late", 5, 5, 0, millis(0))
+            ]
+        );
+        assert_eq!(
+            report.folded(),
+            "program;(start);0001 op1 1000\nprogram;(start);0002 op2 2000\n"
+        );
+    }
+}
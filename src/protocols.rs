@@ -0,0 +1,212 @@
+use crate::ledstrip_controller::{
+    create_message, ProgramStripCommand, StateStripCommand, COLOR_COMMAND_PREFIX,
+    PROGRAM_COMMAND_PREFIX, STATE_COMMAND_PREFIX,
+};
+use std::fmt::Debug;
+
+/// The encoding of the messages for a kind of controller
+pub trait Protocol: Debug {
+    fn color(&self, r: u8, g: u8, b: u8) -> Vec<u8>;
+    fn state(&self, state: StateStripCommand) -> Vec<u8>;
+    fn program(&self, program: ProgramStripCommand, speed: u8) -> Vec<u8>;
+}
+
+/// The protocol of the "Magic Home" controllers. Every message is
+/// terminated by 0x0f and followed by the byte sum of the message
+#[derive(Debug, Clone, Default)]
+pub struct MagicHomeProtocol;
+
+impl Protocol for MagicHomeProtocol {
+    fn color(&self, r: u8, g: u8, b: u8) -> Vec<u8> {
+        create_message(&[COLOR_COMMAND_PREFIX, r, g, b, 0xf0])
+    }
+
+    fn state(&self, state: StateStripCommand) -> Vec<u8> {
+        create_message(&[STATE_COMMAND_PREFIX, state as u8])
+    }
+
+    fn program(&self, program: ProgramStripCommand, speed: u8) -> Vec<u8> {
+        create_message(&[PROGRAM_COMMAND_PREFIX, program as u8, speed])
+    }
+}
+
+/// The protocol of controllers with an additional warm white channel.
+/// The part of the color that is shared by red, green and blue is
+/// shown with the white leds instead
+#[derive(Debug, Clone, Default)]
+pub struct RgbwProtocol;
+
+impl Protocol for RgbwProtocol {
+    fn color(&self, r: u8, g: u8, b: u8) -> Vec<u8> {
+        let w = r.min(g).min(b);
+        create_message(&[COLOR_COMMAND_PREFIX, r - w, g - w, b - w, w, 0x00])
+    }
+
+    fn state(&self, state: StateStripCommand) -> Vec<u8> {
+        MagicHomeProtocol.state(state)
+    }
+
+    fn program(&self, program: ProgramStripCommand, speed: u8) -> Vec<u8> {
+        MagicHomeProtocol.program(program, speed)
+    }
+}
+
+/// The names of the protocols as used in backend descriptions
+pub const PROTOCOL_NAMES: [&str; 2] = ["magic_home", "rgbw"];
+
+/// Returns the protocol with the given name
+pub fn protocol_from_name(name: &str) -> Option<Box<dyn Protocol>> {
+    match name {
+        "magic_home" => Some(Box::new(MagicHomeProtocol)),
+        "rgbw" => Some(Box::new(RgbwProtocol)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::{SimulatorBackend, StripBackend, UdpBackend};
+    use crate::ledstrip_controller::{
+        create_status_response, decode_message, decode_status, StripMessage, StripStatus,
+        STATUS_QUERY, STATUS_RESPONSE_LENGTH,
+    };
+    use std::net::UdpSocket;
+
+    const STATUS_ON: [u8; 14] = [
+        0x81, 0x04, 0x23, 0x61, 0x00, 0x01, 0x12, 0x34, 0x56, 0x00, 0x00, 0x00, 0x0f, 0xb5,
+    ];
+
+    #[test]
+    fn magic_home_colors() {
+        assert_eq!(
+            MagicHomeProtocol.color(0xff, 0x00, 0x00),
+            [0x31, 0xff, 0x00, 0x00, 0xf0, 0x0f, 0x2f]
+        );
+        assert_eq!(
+            MagicHomeProtocol.color(0x12, 0x34, 0x56),
+            [0x31, 0x12, 0x34, 0x56, 0xf0, 0x0f, 0xcc]
+        );
+    }
+
+    #[test]
+    fn magic_home_states() {
+        assert_eq!(
+            MagicHomeProtocol.state(StateStripCommand::On),
+            [0x71, 0x23, 0x0f, 0xa3]
+        );
+        assert_eq!(
+            MagicHomeProtocol.state(StateStripCommand::Off),
+            [0x71, 0x24, 0x0f, 0xa4]
+        );
+    }
+
+    #[test]
+    fn magic_home_programs() {
+        assert_eq!(
+            MagicHomeProtocol.program(ProgramStripCommand::SevenCrossFade, 0x05),
+            [0x61, 0x25, 0x05, 0x0f, 0x9a]
+        );
+        assert_eq!(
+            MagicHomeProtocol.program(ProgramStripCommand::SevenJumping, 0x1f),
+            [0x61, 0x38, 0x1f, 0x0f, 0xc7]
+        );
+    }
+
+    #[test]
+    fn rgbw_moves_the_shared_part_to_white() {
+        assert_eq!(
+            RgbwProtocol.color(0xff, 0x80, 0x40),
+            [0x31, 0xbf, 0x40, 0x00, 0x40, 0x00, 0x0f, 0x7f]
+        );
+        assert_eq!(
+            RgbwProtocol.color(0xff, 0xff, 0xff),
+            [0x31, 0x00, 0x00, 0x00, 0xff, 0x00, 0x0f, 0x3f]
+        );
+        assert_eq!(
+            RgbwProtocol.color(0x12, 0x00, 0x00),
+            [0x31, 0x12, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x52]
+        );
+        assert_eq!(
+            decode_message(&RgbwProtocol.color(0xff, 0x80, 0x40)),
+            Some(StripMessage::ColorWhite(0xbf, 0x40, 0x00, 0x40))
+        );
+    }
+
+    #[test]
+    fn rgbw_shares_states_and_programs() {
+        assert_eq!(
+            RgbwProtocol.state(StateStripCommand::On),
+            [0x71, 0x23, 0x0f, 0xa3]
+        );
+        assert_eq!(
+            RgbwProtocol.program(ProgramStripCommand::SevenCrossFade, 0x05),
+            [0x61, 0x25, 0x05, 0x0f, 0x9a]
+        );
+    }
+
+    #[test]
+    fn status_responses() {
+        let status = StripStatus {
+            on: true,
+            r: 0x12,
+            g: 0x34,
+            b: 0x56,
+        };
+        assert_eq!(STATUS_QUERY, [0x81, 0x8a, 0x8b, 0x96]);
+        assert_eq!(decode_status(&STATUS_ON), Some(status));
+        assert_eq!(create_status_response(&status), STATUS_ON);
+        assert_eq!(
+            decode_status(&[
+                0x81, 0x04, 0x24, 0x61, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x1a,
+            ]),
+            Some(StripStatus::default())
+        );
+
+        let mut corrupted = STATUS_ON;
+        corrupted[STATUS_RESPONSE_LENGTH - 1] = 0xb4;
+        assert_eq!(decode_status(&corrupted), None);
+        assert_eq!(decode_status(&STATUS_ON[..13]), None);
+    }
+
+    #[test]
+    fn udp_sends_one_datagram_per_message() {
+        let controller = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = controller.local_addr().unwrap().to_string();
+        let mut backend = UdpBackend::connect(&address).unwrap();
+
+        backend
+            .send(&MagicHomeProtocol.color(0xff, 0x00, 0x00))
+            .unwrap();
+        backend.send(&STATUS_QUERY).unwrap();
+        let mut buffer = [0u8; 64];
+        let (length, sender) = controller.recv_from(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..length],
+            [0x31, 0xff, 0x00, 0x00, 0xf0, 0x0f, 0x2f]
+        );
+        let (length, _) = controller.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], STATUS_QUERY);
+
+        controller.send_to(&STATUS_ON, sender).unwrap();
+        let mut response = [0u8; STATUS_RESPONSE_LENGTH];
+        backend.receive(&mut response).unwrap();
+        assert_eq!(response, STATUS_ON);
+    }
+
+    #[test]
+    fn the_simulator_answers_with_its_state() {
+        let mut backend = SimulatorBackend::new();
+        backend
+            .send(&MagicHomeProtocol.state(StateStripCommand::On))
+            .unwrap();
+        backend
+            .send(&MagicHomeProtocol.color(0x12, 0x34, 0x56))
+            .unwrap();
+        backend.send(&STATUS_QUERY).unwrap();
+
+        let mut response = [0u8; STATUS_RESPONSE_LENGTH];
+        backend.receive(&mut response).unwrap();
+        assert_eq!(response, STATUS_ON);
+    }
+}
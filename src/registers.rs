@@ -1,6 +1,4 @@
-use crate::ledstrip_controller::{LedStripController, StateStripCommand};
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::fmt::{self, Display, Formatter};
 
 pub const RCS: u8 = 0x01;
 pub const RCR: u8 = 0x02;
@@ -11,28 +9,116 @@ pub const RGP: u8 = 0x06;
 pub const RGI: u8 = 0x07;
 pub const RGO: u8 = 0x08;
 pub const RGL: u8 = 0x09;
+pub const RGS: u8 = 0x0A;
+pub const RHH: u8 = 0x0B;
+pub const RHS: u8 = 0x0C;
+pub const RHV: u8 = 0x0D;
+
+pub const REGISTER_NAMES: [(&str, u8); 13] = [
+    ("rcs", RCS),
+    ("rcr", RCR),
+    ("rcg", RCG),
+    ("rcb", RCB),
+    ("rgd", RGD),
+    ("rgp", RGP),
+    ("rgi", RGI),
+    ("rgo", RGO),
+    ("rgl", RGL),
+    ("rgs", RGS),
+    ("rhh", RHH),
+    ("rhs", RHS),
+    ("rhv", RHV),
+];
 
 /// Maps a register name to the bytecode value
 pub fn get_register_code_by_name(name: &str) -> Option<u8> {
-    let item = [
-        ("rcs", RCS),
-        ("rcr", RCR),
-        ("rcg", RCG),
-        ("rcb", RCB),
-        ("rgd", RGD),
-        ("rgp", RGP),
-        ("rgi", RGI),
-        ("rgo", RGO),
-        ("rgl", RGL),
-    ]
-    .iter()
-    .find(|(reg, _)| *reg == name);
-
-    if let Some(item) = item {
-        Some((*item).1)
-    } else {
-        println!("Unknown register: {}", name);
-        None
+    REGISTER_NAMES
+        .iter()
+        .find(|(reg, _)| *reg == name)
+        .map(|(_, code)| *code)
+}
+
+/// Maps a bytecode value to the name of the register
+pub fn get_register_name_by_code(code: u8) -> Option<&'static str> {
+    REGISTER_NAMES
+        .iter()
+        .find(|(_, reg)| *reg == code)
+        .map(|(name, _)| *name)
+}
+
+/// Returns the size in bytes of the register referenced by the code
+pub fn get_register_size(code: u8) -> Option<usize> {
+    match code {
+        RCS | RCR | RCG | RCB | RHS | RHV => Some(1),
+        RGD | RGP | RGI | RGO | RGL | RGS | RHH => Some(4),
+        _ => None,
+    }
+}
+
+/// Returns if the given bytecode value references a register
+pub fn is_register_code(code: u8) -> bool {
+    REGISTER_NAMES.iter().any(|(_, reg)| *reg == code)
+}
+
+/// A copy of the values of all registers at a point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterSnapshot {
+    pub rcs: bool,
+    pub rcr: u8,
+    pub rcg: u8,
+    pub rcb: u8,
+    pub rgd: u32,
+    pub rgp: u32,
+    pub rgi: u32,
+    pub rgo: u32,
+    pub rgl: u32,
+    pub rgs: u32,
+    pub rhh: u32,
+    pub rhs: u8,
+    pub rhv: u8,
+}
+
+impl RegisterSnapshot {
+    /// Returns the code and value of every register
+    pub fn values(&self) -> Vec<(u8, u32)> {
+        vec![
+            (RCS, self.rcs as u32),
+            (RCR, self.rcr as u32),
+            (RCG, self.rcg as u32),
+            (RCB, self.rcb as u32),
+            (RGD, self.rgd),
+            (RGP, self.rgp),
+            (RGI, self.rgi),
+            (RGO, self.rgo),
+            (RGL, self.rgl),
+            (RGS, self.rgs),
+            (RHH, self.rhh),
+            (RHS, self.rhs as u32),
+            (RHV, self.rhv as u32),
+        ]
+    }
+}
+
+impl Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rcs: {}, rcr: {}, rcg: {}, rcb: {}, rgd: {}, rgp: {}, rgi: {}, rgo: {}, rgl: {}, rgs: {}, \
+             rhh: {}, rhs: {}, rhv: {}",
+            self.rcs,
+            self.rcr,
+            self.rcg,
+            self.rcb,
+            self.rgd,
+            self.rgp,
+            self.rgi,
+            self.rgo,
+            self.rgl,
+            self.rgs,
+            self.rhh,
+            self.rhs,
+            self.rhv
+        )
     }
 }
 
@@ -41,58 +127,74 @@ pub trait Register<T> {
     fn get(&self) -> T;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rcs {
     value: bool,
-    strip_controller: Rc<RefCell<LedStripController>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rcr {
     value: u8,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rcg {
     value: u8,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rcb {
     value: u8,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rgd {
     value: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rgp {
     value: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rgi {
     value: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rgo {
     value: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Rgl {
     value: u32,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Rgs {
+    value: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rhh {
+    value: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rhs {
+    value: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rhv {
+    value: u8,
+}
+
 impl Rcs {
-    pub fn new(strip_controller: Rc<RefCell<LedStripController>>) -> Self {
-        Self {
-            value: false,
-            strip_controller,
-        }
+    pub fn new() -> Self {
+        Self { value: false }
     }
 }
 
@@ -144,18 +246,33 @@ impl Rgl {
     }
 }
 
+impl Rgs {
+    pub fn new() -> Self {
+        Self { value: 0 }
+    }
+}
+
+impl Rhh {
+    pub fn new() -> Self {
+        Self { value: 0 }
+    }
+}
+
+impl Rhs {
+    pub fn new() -> Self {
+        Self { value: 0 }
+    }
+}
+
+impl Rhv {
+    pub fn new() -> Self {
+        Self { value: 0 }
+    }
+}
+
 impl Register<bool> for Rcs {
     fn set(&mut self, value: bool) {
         self.value = value;
-        let state = if value {
-            StateStripCommand::On
-        } else {
-            StateStripCommand::Off
-        };
-        self.strip_controller
-            .borrow_mut()
-            .set_state(state)
-            .expect("failed to set state");
     }
 
     fn get(&self) -> bool {
@@ -242,3 +359,43 @@ impl Register<u32> for Rgl {
         self.value
     }
 }
+
+impl Register<u32> for Rgs {
+    fn set(&mut self, value: u32) {
+        self.value = value;
+    }
+
+    fn get(&self) -> u32 {
+        self.value
+    }
+}
+
+impl Register<u32> for Rhh {
+    fn set(&mut self, value: u32) {
+        self.value = value;
+    }
+
+    fn get(&self) -> u32 {
+        self.value
+    }
+}
+
+impl Register<u8> for Rhs {
+    fn set(&mut self, value: u8) {
+        self.value = value;
+    }
+
+    fn get(&self) -> u8 {
+        self.value
+    }
+}
+
+impl Register<u8> for Rhv {
+    fn set(&mut self, value: u8) {
+        self.value = value;
+    }
+
+    fn get(&self) -> u8 {
+        self.value
+    }
+}
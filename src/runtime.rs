@@ -1,23 +1,93 @@
-use crate::ledstrip_controller::{LedStripController, StateStripCommand};
+use crate::backends::StripBackend;
+use crate::clock::{real_clock, Clock};
+use crate::container::{Container, SourceMap};
+use crate::errors::{FaultKind, ParseError, VmFault, VmResult};
+use crate::fade::{Easing, FADE_BACKGROUND};
+use crate::ledstrip_controller::{LedStripController, Pixel, StateStripCommand};
+use crate::limiter::{FrameStats, LimiterOptions};
+use crate::profiler::{Profile, ProfileReport};
 use crate::registers::{
-    Rcb, Rcg, Rcr, Rcs, Register, Rgd, Rgi, Rgl, Rgo, Rgp, RCB, RCG, RCR, RGD, RGI, RGL, RGO, RGP,
+    get_register_size, is_register_code, Rcb, Rcg, Rcr, Rcs, Register, RegisterSnapshot, Rgd, Rgi,
+    Rgl, Rgo, Rgp, Rgs, Rhh, Rhs, Rhv, RCB, RCG, RCR, RCS, RGD, RGI, RGL, RGO, RGP, RGS, RHH, RHS,
+    RHV,
 };
 use crate::tokens::{
-    AddToken, AndToken, ClearToken, CmdToken, CopyToken, DebugToken, DivToken, ExitToken,
-    FromBytecode, GotoToken, JeToken, JgToken, JlToken, LabelToken, LoadToken, LshToken, ModToken,
-    MulToken, NotToken, NrtToken, OrToken, PauseToken, PowToken, PrintToken, RshToken, SendToken,
-    SetToken, SubToken, Token, WriteToken, XorToken, T_ADD, T_AND, T_CLEAR, T_CMD, T_COPY, T_DEBUG,
-    T_DIV, T_EXIT, T_GOTO, T_JE, T_JG, T_JL, T_LABEL, T_LOAD, T_LSH, T_MOD, T_MUL, T_NOT, T_NRT,
-    T_OR, T_PAUSE, T_POW, T_PRINT, T_RSH, T_SEND, T_SET, T_SUB, T_WRITE, T_XOR,
+    instruction_length, mnemonic, AddToken, AndToken, ClearToken, CmdToken, CopyToken, DebugToken,
+    DivToken, ExitToken, FadeToken, FillToken, FromBytecode, GotoToken, HslToken, HsvToken,
+    JeToken, JgToken, JlToken, LabelToken, LoadToken, LshToken, ModToken, MulToken, NotToken,
+    NrtToken, OrToken, OverflowToken, PauseToken, PixelToken, PowToken, PrintToken, RgbHslToken,
+    RgbHsvToken, RotateToken, RshToken, SendToken, SetToken, SetwToken, ShiftToken, ShowToken,
+    StatusToken, SubToken, Token, WriteToken, XorToken, T_ADD, T_AND, T_CLEAR, T_CMD, T_COPY,
+    T_DEBUG, T_DIV, T_EXIT, T_FADE, T_FILL, T_GOTO, T_HSL, T_HSV, T_JE, T_JG, T_JL, T_LABEL,
+    T_LOAD, T_LSH, T_MOD, T_MUL, T_NOP, T_NOT, T_NRT, T_OR, T_OVERFLOW, T_PAUSE, T_PIXEL, T_POW,
+    T_PRINT, T_RGBHSL, T_RGBHSV, T_ROTATE, T_RSH, T_SEND, T_SET, T_SETW, T_SHIFT, T_SHOW, T_STATUS,
+    T_SUB, T_WRITE, T_XOR,
 };
+use crate::trace::{TraceBackend, TraceEvent, TraceWriter};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The version of the vm that is compared to the minimum version of a container
+pub const VM_VERSION: u16 = 1;
+
+/// Defines how arithmetic results that don't fit into
+/// the target register are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowMode {
+    Wrap = 0x01,
+    Saturate = 0x02,
+    Trap = 0x03,
+}
+
+impl OverflowMode {
+    /// Returns the overflow mode for a bytecode value
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0x01 => Some(OverflowMode::Wrap),
+            0x02 => Some(OverflowMode::Saturate),
+            0x03 => Some(OverflowMode::Trap),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the overflow mode as used in the assembly
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverflowMode::Wrap => "wrap",
+            OverflowMode::Saturate => "saturate",
+            OverflowMode::Trap => "trap",
+        }
+    }
+}
+
+impl FromStr for OverflowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(OverflowMode::Wrap),
+            "saturate" => Ok(OverflowMode::Saturate),
+            "trap" => Ok(OverflowMode::Trap),
+            _ => Err(format!("unknown overflow mode {}", s)),
+        }
+    }
+}
+
+/// A strip the program can select with the rgs register
+#[derive(Debug)]
+pub struct Strip {
+    pub name: String,
+    pub controller: LedStripController,
+    /// The state register of the strip
+    pub rcs: Rcs,
+}
 
 #[derive(Clone)]
 pub struct Runtime {
-    pub rcs: Rcs,
     pub rcr: Rcr,
     pub rcg: Rcg,
     pub rcb: Rcb,
@@ -26,23 +96,65 @@ pub struct Runtime {
     pub rgi: Rgi,
     pub rgo: Rgo,
     pub rgl: Rgl,
+    pub rgs: Rgs,
+    pub rhh: Rhh,
+    pub rhs: Rhs,
+    pub rhv: Rhv,
     pub memory: HashMap<u32, u32>,
     text: Rc<RefCell<Vec<Box<dyn Token>>>>,
     pub labels: HashMap<u32, usize>,
-    pub strip_controller: Rc<RefCell<LedStripController>>,
+    pub symbols: HashMap<u32, String>,
+    source_map: Option<SourceMap>,
+    trace: Option<Rc<RefCell<TraceWriter>>>,
+    profile: Option<Profile>,
+    clock: Rc<dyn Clock>,
+    /// The time of the clock spent sleeping in pause and fades
+    waited: Duration,
+    /// The real time spent sleeping, which differs from waited for virtual clocks
+    slept: Duration,
+    pub strips: Rc<RefCell<Vec<Strip>>>,
     exit: Option<u8>,
+    /// The fault that stopped the program
+    fault: Option<VmFault>,
     current_index: usize,
     debug: bool,
+    overflow_mode: OverflowMode,
+    allow_legacy: bool,
+    skip_pause: bool,
 }
 
 impl Runtime {
-    pub fn new(ip: &str, port: usize) -> Self {
-        let controller = LedStripController::new(ip, port)
-            .expect("failed to establish a connection to the led strip");
-        let controller = Rc::new(RefCell::new(controller));
+    pub fn new(backend: Box<dyn StripBackend>) -> Self {
+        Self::with_strips(vec![("main".to_string(), backend)])
+    }
+
+    /// Creates a runtime that controls several strips. The index
+    /// of a strip in the list is the value for selecting it with rgs
+    pub fn with_strips(backends: Vec<(String, Box<dyn StripBackend>)>) -> Self {
+        Self::with_controllers(
+            backends
+                .into_iter()
+                .map(|(name, backend)| (name, LedStripController::new(backend)))
+                .collect(),
+        )
+    }
+
+    /// Creates a runtime for strips with already configured controllers
+    pub fn with_controllers(controllers: Vec<(String, LedStripController)>) -> Self {
+        let clock = real_clock();
+        let strips = controllers
+            .into_iter()
+            .map(|(name, mut controller)| {
+                controller.set_clock(clock.clone());
+                Strip {
+                    name,
+                    controller,
+                    rcs: Rcs::new(),
+                }
+            })
+            .collect();
 
         Self {
-            rcs: Rcs::new(controller.clone()),
             rcr: Rcr::new(),
             rcg: Rcg::new(),
             rcb: Rcb::new(),
@@ -51,13 +163,28 @@ impl Runtime {
             rgi: Rgi::new(),
             rgo: Rgo::new(),
             rgl: Rgl::new(),
+            rgs: Rgs::new(),
+            rhh: Rhh::new(),
+            rhs: Rhs::new(),
+            rhv: Rhv::new(),
             memory: HashMap::new(),
             text: Rc::new(RefCell::new(Vec::new())),
             labels: HashMap::new(),
-            strip_controller: controller,
+            symbols: HashMap::new(),
+            source_map: None,
+            trace: None,
+            profile: None,
+            clock,
+            waited: Duration::from_secs(0),
+            slept: Duration::from_secs(0),
+            strips: Rc::new(RefCell::new(strips)),
             exit: None,
+            fault: None,
             current_index: 0,
             debug: false,
+            overflow_mode: OverflowMode::Wrap,
+            allow_legacy: false,
+            skip_pause: false,
         }
     }
 
@@ -66,52 +193,163 @@ impl Runtime {
         self.debug = debug;
     }
 
-    /// Parses a vector containing the bytecode into a vector of tokens
-    /// that can be executed
-    pub fn parse_bytecode(&mut self, bytecode: Vec<u8>) {
-        let mut code_iter = bytecode.iter();
+    /// Sets the mode for handling arithmetic overflows.
+    /// This overrides an overflow directive in the bytecode
+    /// if called after parsing
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.overflow_mode = mode;
+    }
+
+    /// Sets the number of pixels in the frame buffer of every strip
+    pub fn set_pixel_count(&mut self, count: usize) {
+        for strip in self.strips.borrow_mut().iter_mut() {
+            strip.controller.pixels.resize(count, (0, 0, 0));
+        }
+    }
+
+    /// Limits the rate of the colors sent to every strip
+    pub fn set_frame_limit(&mut self, options: LimiterOptions) {
+        for strip in self.strips.borrow_mut().iter_mut() {
+            strip.controller.set_limiter(options.clone());
+        }
+    }
+
+    /// Returns the statistics of the frame limiter for every strip
+    pub fn frame_stats(&self) -> Vec<(String, FrameStats)> {
+        self.strips
+            .borrow()
+            .iter()
+            .map(|strip| (strip.name.clone(), strip.controller.limiter.stats))
+            .collect()
+    }
+
+    /// Sends the colors that were held back by the frame limiter and are due now
+    pub fn flush_frames(&mut self) -> VmResult<()> {
+        let mut result = Ok(());
+        for strip in self.strips.borrow_mut().iter_mut() {
+            result = result.and(strip.controller.flush(false));
+        }
+
+        result.map_err(|e| self.fault(FaultKind::Io(e)))
+    }
+
+    /// Sets if bytecode without a container header is accepted
+    pub fn set_allow_legacy(&mut self, allow_legacy: bool) {
+        self.allow_legacy = allow_legacy;
+    }
+
+    /// Parses a bytecode container into a vector of tokens that can be executed.
+    /// The initial memory and label names of the container are loaded as well
+    pub fn parse_bytecode(&mut self, bytecode: &[u8]) -> Result<(), ParseError> {
+        if Container::is_container(bytecode) {
+            let container = Container::from_bytes(bytecode)?;
+            if container.min_vm_version > VM_VERSION {
+                return Err(ParseError::IncompatibleVmVersion {
+                    offset: 6,
+                    required: container.min_vm_version,
+                });
+            }
+            if let Some(mode) = container.overflow_mode {
+                self.overflow_mode = mode;
+            }
+            self.memory.extend(container.data);
+            self.symbols.extend(container.symbols);
+            self.source_map = container.source_map;
+
+            self.parse_text(
+                &container.code,
+                container.code_offset,
+                container.overflow_mode.is_some(),
+            )
+        } else if self.allow_legacy {
+            self.parse_text(bytecode, 0, false)
+        } else {
+            Err(ParseError::MissingHeader)
+        }
+    }
+
+    /// Parses raw bytecode into tokens. The base offset is added
+    /// to the offsets of errors. The overflow mode can only be set
+    /// by the bytecode if the container doesn't set it already
+    fn parse_text(
+        &mut self,
+        bytecode: &[u8],
+        base_offset: usize,
+        mut overflow_set: bool,
+    ) -> Result<(), ParseError> {
         let mut text = self.text.borrow_mut();
-        let mut index = 0;
-
-        while let Some(instruction) = code_iter.next() {
-            match *instruction {
-                T_EXIT => text.push(Box::new(ExitToken::from_bytecode(&[
-                    instruction,
-                    code_iter.next().unwrap(),
-                ]))),
-                T_SET => text.push(Box::new(SetToken::from_bytecode(&[
-                    instruction,
-                    code_iter.next().unwrap(),
-                    code_iter.next().unwrap(),
-                ]))),
-                T_COPY => text.push(Box::new(CopyToken::from_bytecode(&[
-                    instruction,
-                    code_iter.next().unwrap(),
-                    code_iter.next().unwrap(),
-                ]))),
+        let mut position = 0;
+
+        while position < bytecode.len() {
+            let offset = base_offset + position;
+            let opcode = bytecode[position];
+            let length =
+                instruction_length(opcode).ok_or(ParseError::UnknownOpcode { offset, opcode })?;
+            let code: Vec<&u8> = bytecode
+                .get(position..position + length)
+                .ok_or(ParseError::TruncatedOperand { offset, opcode })?
+                .iter()
+                .collect();
+            let check_register = |register: u8| {
+                if is_register_code(register) {
+                    Ok(register)
+                } else {
+                    Err(ParseError::InvalidRegister {
+                        offset,
+                        opcode,
+                        register,
+                    })
+                }
+            };
+
+            match opcode {
+                T_EXIT => {
+                    check_register(*code[1])?;
+                    text.push(Box::new(ExitToken::from_bytecode(&code)))
+                }
+                T_SET => {
+                    check_register(*code[2])?;
+                    text.push(Box::new(SetToken::from_bytecode(&code)))
+                }
+                T_SETW => {
+                    let register = check_register(*code[5])?;
+                    if get_register_size(register) != Some(4) {
+                        return Err(ParseError::InvalidRegister {
+                            offset,
+                            opcode,
+                            register,
+                        });
+                    }
+                    text.push(Box::new(SetwToken::from_bytecode(&code)))
+                }
+                T_COPY => {
+                    check_register(*code[1])?;
+                    check_register(*code[2])?;
+                    text.push(Box::new(CopyToken::from_bytecode(&code)))
+                }
                 T_LOAD => text.push(Box::new(LoadToken)),
-                T_CLEAR => text.push(Box::new(ClearToken::from_bytecode(&[
-                    instruction,
-                    code_iter.next().unwrap(),
-                ]))),
+                T_CLEAR => {
+                    check_register(*code[1])?;
+                    text.push(Box::new(ClearToken::from_bytecode(&code)))
+                }
                 T_WRITE => text.push(Box::new(WriteToken)),
                 T_LABEL => {
-                    let token = LabelToken::from_bytecode(&[
-                        instruction,
-                        code_iter.next().unwrap(),
-                        code_iter.next().unwrap(),
-                        code_iter.next().unwrap(),
-                        code_iter.next().unwrap(),
-                    ]);
-                    self.labels.insert(token.value, index);
+                    let token = LabelToken::from_bytecode(&code);
+                    if self.labels.insert(token.value, text.len()).is_some() {
+                        return Err(ParseError::DuplicateLabel {
+                            offset,
+                            opcode,
+                            label: token.value,
+                        });
+                    }
                     text.push(Box::new(token));
                 }
                 T_GOTO => text.push(Box::new(GotoToken)),
                 T_DEBUG => text.push(Box::new(DebugToken)),
-                T_PRINT => text.push(Box::new(PrintToken::from_bytecode(&[
-                    instruction,
-                    code_iter.next().unwrap(),
-                ]))),
+                T_PRINT => {
+                    check_register(*code[1])?;
+                    text.push(Box::new(PrintToken::from_bytecode(&code)))
+                }
                 T_ADD => text.push(Box::new(AddToken)),
                 T_SUB => text.push(Box::new(SubToken)),
                 T_MUL => text.push(Box::new(MulToken)),
@@ -128,42 +366,436 @@ impl Runtime {
                 T_JG => text.push(Box::new(JgToken)),
                 T_JL => text.push(Box::new(JlToken)),
                 T_JE => text.push(Box::new(JeToken)),
+                T_PIXEL => text.push(Box::new(PixelToken)),
+                T_FILL => text.push(Box::new(FillToken)),
+                T_SHIFT => text.push(Box::new(ShiftToken)),
+                T_ROTATE => text.push(Box::new(RotateToken)),
+                T_SHOW => text.push(Box::new(ShowToken)),
+                T_OVERFLOW => {
+                    if overflow_set {
+                        return Err(ParseError::DuplicateOverflowMode { offset, opcode });
+                    }
+                    overflow_set = true;
+                    let mode =
+                        OverflowMode::from_code(*code[1]).ok_or(ParseError::InvalidOperand {
+                            offset,
+                            opcode,
+                            operand: *code[1],
+                        })?;
+                    self.overflow_mode = mode;
+                    text.push(Box::new(OverflowToken { mode }));
+                }
+                T_FADE => {
+                    let easing = Easing::from_code(*code[1] & !FADE_BACKGROUND).ok_or(
+                        ParseError::InvalidOperand {
+                            offset,
+                            opcode,
+                            operand: *code[1],
+                        },
+                    )?;
+                    text.push(Box::new(FadeToken {
+                        easing,
+                        background: *code[1] & FADE_BACKGROUND != 0,
+                    }));
+                }
+                T_HSV => text.push(Box::new(HsvToken)),
+                T_HSL => text.push(Box::new(HslToken)),
+                T_RGBHSV => text.push(Box::new(RgbHsvToken)),
+                T_RGBHSL => text.push(Box::new(RgbHslToken)),
                 T_PAUSE => text.push(Box::new(PauseToken)),
                 T_CMD => text.push(Box::new(CmdToken)),
                 T_SEND => text.push(Box::new(SendToken)),
-                _ => panic!("unknown instruction {}", instruction),
+                T_STATUS => text.push(Box::new(StatusToken)),
+                _ => return Err(ParseError::UnknownOpcode { offset, opcode }),
             };
-            index += 1;
+            position += length;
         }
+
+        Ok(())
     }
 
     /// Executes the text stored in the runtime
-    pub fn run(&mut self) -> io::Result<u8> {
+    pub fn run(&mut self) -> VmResult<u8> {
+        loop {
+            if let Some(code) = self.step()? {
+                return Ok(code);
+            }
+        }
+    }
+
+    /// Executes the next instruction. Returns the exit code
+    /// once the program is finished and the strips are turned off.
+    /// After a fault every further step returns the fault again
+    pub fn step(&mut self) -> VmResult<Option<u8>> {
+        if let Some(fault) = &self.fault {
+            return Err(fault.clone());
+        }
+        if let Some(code) = self.exit {
+            return Ok(Some(code));
+        }
         let text_ref = self.text.clone();
-        let text = text_ref.borrow_mut();
-        while self.current_index < text.len() {
-            let token = text.get(self.current_index).unwrap();
+        let text = text_ref.borrow();
+        if let Some(token) = text.get(self.current_index) {
             if self.debug {
-                println!("{:0>4}: {:?}", self.current_index, token);
+                match self.source_location(self.current_index) {
+                    Some(location) => {
+                        let opcode = token.to_bytecode()[0];
+                        let name = mnemonic(opcode).unwrap_or("?");
+                        println!("{:0>4}: {} {}", self.current_index, location, name);
+                    }
+                    None => println!("{:0>4}: {:?}", self.current_index, token),
+                }
+            }
+            let registers = if self.trace.is_some() {
+                self.record(TraceEvent::Instruction {
+                    index: self.current_index,
+                    opcode: token.to_bytecode()[0],
+                })?;
+                Some(self.snapshot())
+            } else {
+                None
+            };
+            let (index, start) = (self.current_index, Instant::now());
+            let (waited, slept) = (self.waited, self.slept);
+            let result = token
+                .invoke(self)
+                .and_then(|_| self.advance_fades().map(|_| ()));
+            if let Some(profile) = &mut self.profile {
+                let compute = start.elapsed().saturating_sub(self.slept - slept);
+                profile.record(index, compute, self.waited - waited);
+            }
+            if let Err(fault) = result {
+                let _ = self.turn_off();
+                self.fault = Some(fault.clone());
+                let _ = self.record(TraceEvent::Fault(fault.kind.to_string()));
+                let _ = self.flush_trace();
+                return Err(fault);
             }
-            token.invoke(self)?;
+            if let Some(registers) = registers {
+                self.record_register_changes(&registers)?;
+            }
+            if self.exit.is_none() {
+                self.current_index += 1;
+            }
+        }
 
-            if let Some(code) = self.exit {
-                self.strip_controller
-                    .borrow_mut()
-                    .set_state(StateStripCommand::Off)
-                    .unwrap();
-                return Ok(code);
+        if self.exit.is_none() && self.current_index >= text.len() {
+            self.exit = Some(0);
+        }
+        match self.exit {
+            Some(code) => {
+                self.turn_off().map_err(|e| self.fault(FaultKind::Io(e)))?;
+                self.record(TraceEvent::Exit(code))?;
+                self.flush_trace()?;
+                Ok(Some(code))
             }
+            None => Ok(None),
+        }
+    }
 
-            self.current_index += 1;
+    /// Records the events of the program into the trace.
+    /// Has to be called after the bytecode is parsed so the labels are known
+    pub fn set_trace(&mut self, mut trace: TraceWriter) -> VmResult<()> {
+        trace.set_clock(self.clock.clone());
+        let trace = Rc::new(RefCell::new(trace));
+        for (number, strip) in self.strips.borrow_mut().iter_mut().enumerate() {
+            let trace = trace.clone();
+            strip
+                .controller
+                .wrap_backend(|backend| Box::new(TraceBackend::new(backend, number, trace)));
         }
+        self.trace = Some(trace);
 
-        self.strip_controller
-            .borrow_mut()
-            .set_state(StateStripCommand::Off)
-            .unwrap();
-        Ok(0)
+        let mut labels: Vec<(u32, usize)> = self
+            .labels
+            .iter()
+            .map(|(id, index)| (*id, *index))
+            .collect();
+        labels.sort_by_key(|(_, index)| *index);
+        for (id, index) in labels {
+            let name = self.symbols.get(&id).cloned();
+            self.record(TraceEvent::Label { id, index, name })?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the clock used for pauses, the frame limiter, fades and timestamps.
+    /// A virtual clock lets pauses return immediately while the time still advances
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        for strip in self.strips.borrow_mut().iter_mut() {
+            strip.controller.set_clock(clock.clone());
+        }
+        if let Some(trace) = &self.trace {
+            trace.borrow_mut().set_clock(clock.clone());
+        }
+        self.clock = clock;
+    }
+
+    /// Returns the current time of the clock
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Counts the executions and the time of every instruction
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profile = if profiling {
+            Some(Profile::new(self.instruction_count()))
+        } else {
+            None
+        };
+    }
+
+    /// Returns the profile of the execution so far if profiling is enabled.
+    /// The blocks start at the labels of the program
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        let profile = self.profile.as_ref()?;
+        let instructions = (0..self.instruction_count())
+            .map(|index| {
+                let opcode = self.instruction(index).map_or(T_NOP, |code| code[0]);
+                let name = mnemonic(opcode).unwrap_or("?").to_string();
+                (name, self.source_location(index))
+            })
+            .collect();
+        let blocks = self
+            .labels
+            .iter()
+            .map(|(id, index)| {
+                let name = match self.symbols.get(id) {
+                    Some(name) => name.clone(),
+                    None => format!("label 0x{:X}", id),
+                };
+                (*index, name)
+            })
+            .collect();
+
+        Some(profile.report(instructions, blocks))
+    }
+
+    /// Writes the event into the trace if there is one
+    fn record(&self, event: TraceEvent) -> VmResult<()> {
+        if let Some(trace) = &self.trace {
+            let result = trace.borrow_mut().record(event);
+            result.map_err(|e| self.fault(FaultKind::Io(e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the registers that differ from the previous values
+    fn record_register_changes(&self, previous: &RegisterSnapshot) -> VmResult<()> {
+        let current = self.snapshot().values();
+        for ((code, value), (_, previous)) in current.into_iter().zip(previous.values()) {
+            if value != previous {
+                self.record(TraceEvent::Register { code, value })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush_trace(&self) -> VmResult<()> {
+        if let Some(trace) = &self.trace {
+            let result = trace.borrow_mut().flush();
+            result.map_err(|e| self.fault(FaultKind::Io(e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a value into memory
+    pub fn write_memory(&mut self, address: u32, value: u32) -> VmResult<()> {
+        self.memory.insert(address, value);
+        self.record(TraceEvent::Memory { address, value })
+    }
+
+    /// Returns the index of the instruction that is executed next
+    pub fn current_index(&self) -> usize {
+        self.current_index
+    }
+
+    /// Returns the number of instructions of the program
+    pub fn instruction_count(&self) -> usize {
+        self.text.borrow().len()
+    }
+
+    /// Returns the bytecode of the instruction at the index
+    pub fn instruction(&self, index: usize) -> Option<Vec<u8>> {
+        self.text
+            .borrow()
+            .get(index)
+            .map(|token| token.to_bytecode())
+    }
+
+    /// Returns the source file and line of the instruction at the index
+    /// if the program has a source map
+    pub fn source_location(&self, index: usize) -> Option<String> {
+        self.source_map.as_ref()?.location(index)
+    }
+
+    /// Returns if the program has exited or faulted
+    pub fn is_finished(&self) -> bool {
+        self.exit.is_some() || self.fault.is_some()
+    }
+
+    /// Sets if pause instructions return immediately
+    pub fn set_skip_pause(&mut self, skip_pause: bool) {
+        self.skip_pause = skip_pause;
+    }
+
+    /// Sends the due frames of the fades of all strips. Returns the
+    /// time until the next frame or None if no fade is running
+    pub fn advance_fades(&mut self) -> VmResult<Option<Duration>> {
+        let mut result = Ok(None);
+        for strip in self.strips.borrow_mut().iter_mut() {
+            let next = strip.controller.advance_fade();
+            result = result.and_then(|earliest: Option<Duration>| {
+                next.map(|next| match (earliest, next) {
+                    (Some(earliest), Some(next)) => Some(earliest.min(next)),
+                    (earliest, next) => earliest.or(next),
+                })
+            });
+        }
+
+        result.map_err(|e| self.fault(FaultKind::Io(e)))
+    }
+
+    /// Waits for the given duration while the fades keep running
+    pub fn pause(&mut self, duration: Duration) -> VmResult<()> {
+        let duration = if self.skip_pause {
+            Duration::from_secs(0)
+        } else {
+            duration
+        };
+        let end = self.clock.now() + duration;
+        loop {
+            let next_frame = self.advance_fades()?;
+            let remaining = end.saturating_sub(self.clock.now());
+            if remaining == Duration::from_secs(0) {
+                break;
+            }
+            self.wait(next_frame.map_or(remaining, |next| next.min(remaining)));
+        }
+
+        self.flush_frames()
+    }
+
+    /// Sleeps for the duration and adds it to the time spent waiting
+    fn wait(&mut self, duration: Duration) {
+        let (start, slept) = (self.clock.now(), Instant::now());
+        self.clock.sleep(duration);
+        self.waited += self.clock.now() - start;
+        self.slept += slept.elapsed();
+    }
+
+    /// Fades the selected strip to the color in rcr, rcg and rcb within rgd
+    /// milliseconds. Unless it runs in the background this waits until it's finished
+    pub fn fade(&mut self, easing: Easing, background: bool) -> VmResult<()> {
+        let to = (self.rcr.get(), self.rcg.get(), self.rcb.get());
+        let duration = Duration::from_millis(self.rgd.get() as u64);
+        self.with_strip(|strip| {
+            strip.controller.start_fade(to, duration, easing);
+            Ok(())
+        })?;
+
+        if background {
+            return Ok(());
+        }
+        loop {
+            let next_frame = self.advance_fades()?;
+            if !self.with_strip(|strip| Ok(strip.controller.fade.is_some()))? {
+                break;
+            }
+            if let Some(next_frame) = next_frame {
+                self.wait(next_frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the actual state and color of the strip into the
+    /// registers without sending anything back to the strip
+    pub fn load_strip_status(&mut self) -> VmResult<()> {
+        let status = self.with_strip(|strip| strip.controller.query_status())?;
+        self.with_strip(|strip| {
+            strip.rcs.set(status.on);
+            Ok(())
+        })?;
+        self.rcr.set(status.r);
+        self.rcg.set(status.g);
+        self.rcb.set(status.b);
+
+        Ok(())
+    }
+
+    /// Calls the function with the strip selected by rgs
+    pub fn with_strip<T>(&mut self, f: impl FnOnce(&mut Strip) -> io::Result<T>) -> VmResult<T> {
+        let index = self.rgs.get();
+        if index as usize >= self.strips.borrow().len() {
+            return Err(self.fault(FaultKind::UnknownStrip(index)));
+        }
+        let result = f(&mut self.strips.borrow_mut()[index as usize]);
+
+        result.map_err(|e| self.fault(FaultKind::Io(e)))
+    }
+
+    /// Calls the function with the frame buffer of the strip selected by rgs
+    pub fn with_frame<T>(&mut self, f: impl FnOnce(&mut Vec<Pixel>) -> T) -> VmResult<T> {
+        self.with_strip(|strip| Ok(f(&mut strip.controller.pixels)))
+    }
+
+    /// Turns off all strips at the end of the program
+    fn turn_off(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for strip in self.strips.borrow_mut().iter_mut() {
+            let turned_off = strip.controller.set_state(StateStripCommand::Off);
+            result = result.and(turned_off);
+        }
+
+        result
+    }
+
+    /// Creates a fault at the current instruction
+    pub fn fault(&self, kind: FaultKind) -> VmFault {
+        let opcode = self
+            .text
+            .borrow()
+            .get(self.current_index)
+            .map(|token| token.to_bytecode()[0])
+            .unwrap_or(T_NOP);
+
+        VmFault {
+            kind,
+            index: self.current_index,
+            location: self.source_location(self.current_index),
+            opcode,
+            registers: self.snapshot(),
+        }
+    }
+
+    /// Returns a copy of the current register values
+    pub fn snapshot(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            rcs: self
+                .strips
+                .borrow()
+                .get(self.rgs.get() as usize)
+                .map(|strip| strip.rcs.get())
+                .unwrap_or(false),
+            rcr: self.rcr.get(),
+            rcg: self.rcg.get(),
+            rcb: self.rcb.get(),
+            rgd: self.rgd.get(),
+            rgp: self.rgp.get(),
+            rgi: self.rgi.get(),
+            rgo: self.rgo.get(),
+            rgl: self.rgl.get(),
+            rgs: self.rgs.get(),
+            rhh: self.rhh.get(),
+            rhs: self.rhs.get(),
+            rhv: self.rhv.get(),
+        }
     }
 
     /// Exists the program with a specified error code
@@ -177,6 +809,8 @@ impl Runtime {
             RCR => Some(Box::new(&mut self.rcr)),
             RCG => Some(Box::new(&mut self.rcg)),
             RCB => Some(Box::new(&mut self.rcb)),
+            RHS => Some(Box::new(&mut self.rhs)),
+            RHV => Some(Box::new(&mut self.rhv)),
             _ => None,
         }
     }
@@ -189,6 +823,8 @@ impl Runtime {
             RGI => Some(Box::new(&mut self.rgi)),
             RGO => Some(Box::new(&mut self.rgo)),
             RGL => Some(Box::new(&mut self.rgl)),
+            RGS => Some(Box::new(&mut self.rgs)),
+            RHH => Some(Box::new(&mut self.rhh)),
             _ => None,
         }
     }
@@ -198,13 +834,283 @@ impl Runtime {
         self.labels.insert(id, self.current_index);
     }
 
-    /// Jumps to a specified label
-    pub fn jump(&mut self, label: u32) -> io::Result<()> {
-        self.current_index = *self
-            .labels
-            .get(&label)
-            .expect(&format!("The label {} does not exist", label));
+    /// Reads the value of the register referenced by the code
+    pub fn read_register(&mut self, code: u8) -> VmResult<u32> {
+        if let Some(rg) = self.get_1byte_register(code) {
+            Ok(rg.get() as u32)
+        } else if let Some(rg) = self.get_4byte_register(code) {
+            Ok(rg.get())
+        } else if code == RCS {
+            self.with_strip(|strip| Ok(strip.rcs.get() as u32))
+        } else {
+            Err(self.fault(FaultKind::UnknownRegister(code)))
+        }
+    }
+
+    /// Writes a value to the register referenced by the code.
+    /// Values that don't fit into 1byte registers are handled according
+    /// to the overflow mode and writing to the state register turns
+    /// the strip on or off
+    pub fn write_register(&mut self, code: u8, value: u32) -> VmResult<()> {
+        if self.get_1byte_register(code).is_some() {
+            let checked = if value <= 0xFF { Some(value) } else { None };
+            let value = self.overflowing(checked, value & 0xFF, 0xFF)?;
+            self.get_1byte_register(code).unwrap().set(value as u8);
+        } else if let Some(rg) = &mut self.get_4byte_register(code) {
+            rg.set(value);
+        } else if code == RCS {
+            let state = if value != 0 {
+                StateStripCommand::On
+            } else {
+                StateStripCommand::Off
+            };
+            self.with_strip(|strip| {
+                strip.controller.set_state(state)?;
+                strip.rcs.set(value != 0);
+                Ok(())
+            })?;
+        } else {
+            return Err(self.fault(FaultKind::UnknownRegister(code)));
+        }
 
         Ok(())
     }
+
+    /// Returns the result of an arithmetic operation depending on the overflow mode.
+    /// The checked value is None if the operation overflowed
+    pub fn overflowing(&self, checked: Option<u32>, wrapped: u32, saturated: u32) -> VmResult<u32> {
+        match (checked, self.overflow_mode) {
+            (Some(value), _) => Ok(value),
+            (None, OverflowMode::Wrap) => Ok(wrapped),
+            (None, OverflowMode::Saturate) => Ok(saturated),
+            (None, OverflowMode::Trap) => Err(self.fault(FaultKind::ArithmeticOverflow)),
+        }
+    }
+
+    /// Jumps to a specified label
+    pub fn jump(&mut self, label: u32) -> VmResult<()> {
+        if let Some(index) = self.labels.get(&label) {
+            self.current_index = *index;
+            Ok(())
+        } else {
+            Err(self.fault(FaultKind::UnknownLabel(label)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::backends::{NullBackend, RecordingBackend};
+    use crate::protocols::{MagicHomeProtocol, Protocol};
+
+    /// Parses plain bytecode without a container
+    fn parse_legacy(bytecode: &[u8]) -> Result<Runtime, ParseError> {
+        let mut runtime = Runtime::new(Box::new(NullBackend));
+        runtime.set_allow_legacy(true);
+        runtime.parse_bytecode(bytecode)?;
+
+        Ok(runtime)
+    }
+
+    #[test]
+    fn valid_bytecode_is_parsed() {
+        let runtime = parse_legacy(&[T_SET, 0x10, RGD, T_LABEL, 0, 0, 0, 1, T_ADD]).unwrap();
+        assert_eq!(runtime.instruction_count(), 3);
+        assert_eq!(runtime.labels.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn truncated_operands_are_rejected() {
+        assert_eq!(
+            parse_legacy(&[T_ADD, T_SET, 0x10]).err(),
+            Some(ParseError::TruncatedOperand {
+                offset: 1,
+                opcode: T_SET
+            })
+        );
+        assert_eq!(
+            parse_legacy(&[T_LABEL, 0, 0]).err(),
+            Some(ParseError::TruncatedOperand {
+                offset: 0,
+                opcode: T_LABEL
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_opcodes_are_rejected() {
+        assert_eq!(
+            parse_legacy(&[T_SET, 0x10, RGD, T_ADD, 0xEE]).err(),
+            Some(ParseError::UnknownOpcode {
+                offset: 4,
+                opcode: 0xEE
+            })
+        );
+    }
+
+    #[test]
+    fn duplicate_labels_are_rejected() {
+        let error = parse_legacy(&[T_LABEL, 0, 0, 0, 2, T_ADD, T_LABEL, 0, 0, 0, 2])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error,
+            ParseError::DuplicateLabel {
+                offset: 6,
+                opcode: T_LABEL,
+                label: 2
+            }
+        );
+        assert_eq!(error.offset(), 6);
+        assert_eq!(error.opcode(), Some(T_LABEL));
+    }
+
+    #[test]
+    fn invalid_registers_are_rejected() {
+        assert_eq!(
+            parse_legacy(&[T_ADD, T_COPY, RGD, 0x7F]).err(),
+            Some(ParseError::InvalidRegister {
+                offset: 1,
+                opcode: T_COPY,
+                register: 0x7F
+            })
+        );
+        assert_eq!(
+            parse_legacy(&[T_SET, 0x01, 0x00]).err(),
+            Some(ParseError::InvalidRegister {
+                offset: 0,
+                opcode: T_SET,
+                register: 0x00
+            })
+        );
+    }
+
+    #[test]
+    fn container_offsets_include_the_header() {
+        let bytecode = Container::new(vec![T_ADD, 0xEE]).to_bytes();
+        let code_offset = Container::from_bytes(&bytecode).unwrap().code_offset;
+        let mut runtime = Runtime::new(Box::new(NullBackend));
+        assert_eq!(
+            runtime.parse_bytecode(&bytecode).err(),
+            Some(ParseError::UnknownOpcode {
+                offset: code_offset + 1,
+                opcode: 0xEE
+            })
+        );
+    }
+
+    #[test]
+    fn faults_report_the_instruction_and_registers() {
+        let mut runtime = parse_legacy(&[
+            T_SET, 0x05, RGD, T_SET, 0x07, RCR, T_CLEAR, RGI, T_DIV, T_ADD,
+        ])
+        .unwrap();
+        let fault = runtime.run().unwrap_err();
+
+        assert!(matches!(fault.kind, FaultKind::DivisionByZero));
+        assert_eq!(fault.index, 3);
+        assert_eq!(fault.opcode, T_DIV);
+        assert_eq!(fault.location, None);
+        assert_eq!(fault.registers.rgd, 5);
+        assert_eq!(fault.registers.rgi, 0);
+        assert_eq!(fault.registers.rcr, 7);
+        assert_eq!(
+            fault.to_string().lines().next(),
+            Some("division by zero at instruction 0003 (opcode 0x13)")
+        );
+    }
+
+    #[test]
+    fn setw_writes_four_bytes_big_endian() {
+        let mut runtime = parse_legacy(&[T_SETW, 0x12, 0x34, 0x56, 0x78, RGD]).unwrap();
+        runtime.run().unwrap();
+        assert_eq!(runtime.read_register(RGD).unwrap(), 0x1234_5678);
+    }
+
+    #[test]
+    fn setw_rejects_one_byte_registers() {
+        assert_eq!(
+            parse_legacy(&[T_ADD, T_SETW, 0x00, 0x00, 0x00, 0x01, RCR]).err(),
+            Some(ParseError::InvalidRegister {
+                offset: 1,
+                opcode: 0x0B,
+                register: RCR
+            })
+        );
+    }
+
+    #[test]
+    fn cmd_checks_the_program_and_speed() {
+        let mut runtime = parse_legacy(&[T_SET, 0x24, RGD, T_SET, 0x01, RGI, T_CMD]).unwrap();
+        let fault = runtime.run().unwrap_err();
+        assert!(matches!(fault.kind, FaultKind::UnknownProgram(0x24)));
+        assert_eq!(fault.opcode, T_CMD);
+
+        let mut runtime = parse_legacy(&[T_SET, 0x25, RGD, T_SET, 0x20, RGI, T_CMD]).unwrap();
+        let fault = runtime.run().unwrap_err();
+        assert!(matches!(fault.kind, FaultKind::InvalidSpeed(0x20)));
+        assert_eq!(
+            fault.kind.to_string(),
+            "invalid program speed 0x20 (expected 0x01 to 0x1F)"
+        );
+
+        let mut runtime = parse_legacy(&[T_SET, 0x25, RGD, T_SET, 0x1F, RGI, T_CMD]).unwrap();
+        assert_eq!(runtime.run().unwrap(), 0);
+    }
+
+    #[test]
+    fn rgs_selects_the_strip() {
+        let kitchen = RecordingBackend::new();
+        let hall = RecordingBackend::new();
+        let mut runtime = Runtime::with_strips(vec![
+            ("kitchen".to_string(), Box::new(kitchen.clone())),
+            ("hall".to_string(), Box::new(hall.clone())),
+        ]);
+        let source = "\
+set 0x01 rcs
+set 0xFF rcr
+send
+set 0x01 rgs
+set 0x01 rcs
+clear rcr
+set 0xFF rcb
+send
+set 0x02 rgs
+send
+";
+        runtime
+            .parse_bytecode(&assemble(source, false).unwrap())
+            .unwrap();
+        let fault = runtime.run().unwrap_err();
+        assert!(matches!(fault.kind, FaultKind::UnknownStrip(2)));
+        assert_eq!(fault.index, 9);
+
+        let on = MagicHomeProtocol.state(StateStripCommand::On);
+        let off = MagicHomeProtocol.state(StateStripCommand::Off);
+        assert_eq!(
+            kitchen.frames(),
+            vec![on.clone(), MagicHomeProtocol.color(0xFF, 0, 0), off.clone()]
+        );
+        assert_eq!(
+            hall.frames(),
+            vec![on, MagicHomeProtocol.color(0, 0, 0xFF), off]
+        );
+    }
+
+    #[test]
+    fn steps_after_a_fault_report_the_fault() {
+        let mut runtime = parse_legacy(&[T_CLEAR, RGI, T_DIV, T_ADD]).unwrap();
+        assert_eq!(runtime.step().unwrap(), None);
+        assert!(runtime.step().is_err());
+        assert!(runtime.is_finished());
+
+        for _ in 0..2 {
+            let fault = runtime.step().unwrap_err();
+            assert!(matches!(fault.kind, FaultKind::DivisionByZero));
+            assert_eq!(fault.index, 1);
+        }
+        assert!(runtime.run().is_err());
+    }
 }
@@ -1,10 +1,14 @@
-use crate::registers::{Register, RCS};
-use crate::runtime::Runtime;
+use crate::color::{hsl_to_rgb, hsv_to_rgb, rgb_to_hsl, rgb_to_hsv};
+use crate::errors::{FaultKind, VmResult};
+use crate::fade::{Easing, FADE_BACKGROUND};
+use crate::ledstrip_controller::{ProgramStripCommand, PROGRAM_SPEEDS};
+use crate::registers::Register;
+use crate::runtime::{OverflowMode, Runtime};
 use num_integer::Roots;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::Debug;
-use std::io;
 use std::ops::BitXor;
-use std::thread::sleep;
 use std::time::Duration;
 
 pub const T_NOP: u8 = 0x00;
@@ -18,6 +22,7 @@ pub const T_LABEL: u8 = 0x07;
 pub const T_GOTO: u8 = 0x08;
 pub const T_DEBUG: u8 = 0x09;
 pub const T_PRINT: u8 = 0x0A;
+pub const T_SETW: u8 = 0x0B;
 pub const T_ADD: u8 = 0x10;
 pub const T_SUB: u8 = 0x11;
 pub const T_MUL: u8 = 0x12;
@@ -34,13 +39,92 @@ pub const T_NRT: u8 = 0x1C;
 pub const T_JG: u8 = 0x20;
 pub const T_JL: u8 = 0x21;
 pub const T_JE: u8 = 0x22;
+pub const T_PIXEL: u8 = 0x30;
+pub const T_FILL: u8 = 0x31;
+pub const T_SHIFT: u8 = 0x32;
+pub const T_ROTATE: u8 = 0x33;
+pub const T_SHOW: u8 = 0x34;
+pub const T_HSV: u8 = 0x40;
+pub const T_HSL: u8 = 0x41;
+pub const T_RGBHSV: u8 = 0x42;
+pub const T_RGBHSL: u8 = 0x43;
+pub const T_OVERFLOW: u8 = 0xE0;
 pub const T_PAUSE: u8 = 0xF0;
 pub const T_CMD: u8 = 0xF1;
 pub const T_SEND: u8 = 0xF2;
+pub const T_STATUS: u8 = 0xF3;
+pub const T_FADE: u8 = 0xF4;
+
+/// Returns the length in bytes of the instruction with the given opcode
+/// including the opcode itself
+pub fn instruction_length(opcode: u8) -> Option<usize> {
+    match opcode {
+        T_SETW => Some(6),
+        T_LABEL => Some(5),
+        T_SET | T_COPY => Some(3),
+        T_EXIT | T_CLEAR | T_PRINT | T_OVERFLOW | T_FADE => Some(2),
+        T_LOAD | T_WRITE | T_GOTO | T_DEBUG | T_ADD | T_SUB | T_MUL | T_DIV | T_MOD | T_LSH
+        | T_RSH | T_AND | T_OR | T_NOT | T_XOR | T_POW | T_NRT | T_JG | T_JL | T_JE | T_PIXEL
+        | T_FILL | T_SHIFT | T_ROTATE | T_SHOW | T_HSV | T_HSL | T_RGBHSV | T_RGBHSL | T_PAUSE
+        | T_CMD | T_SEND | T_STATUS => Some(1),
+        _ => None,
+    }
+}
+
+/// Returns the assembly name of the instruction with the given opcode
+pub fn mnemonic(opcode: u8) -> Option<&'static str> {
+    let name = match opcode {
+        T_EXIT => "exit",
+        T_SET => "set",
+        T_COPY => "copy",
+        T_LOAD => "load",
+        T_CLEAR => "clear",
+        T_WRITE => "write",
+        T_LABEL => "label",
+        T_GOTO => "goto",
+        T_DEBUG => "debug",
+        T_PRINT => "print",
+        T_SETW => "setw",
+        T_ADD => "add",
+        T_SUB => "sub",
+        T_MUL => "mul",
+        T_DIV => "div",
+        T_MOD => "mod",
+        T_LSH => "lsh",
+        T_RSH => "rsh",
+        T_AND => "and",
+        T_OR => "or",
+        T_NOT => "not",
+        T_XOR => "xor",
+        T_POW => "pow",
+        T_NRT => "nrt",
+        T_JG => "jg",
+        T_JL => "jl",
+        T_JE => "je",
+        T_PIXEL => "pixel",
+        T_FILL => "fill",
+        T_SHIFT => "shift",
+        T_ROTATE => "rotate",
+        T_SHOW => "show",
+        T_HSV => "hsv",
+        T_HSL => "hsl",
+        T_RGBHSV => "rgbhsv",
+        T_RGBHSL => "rgbhsl",
+        T_OVERFLOW => ".overflow",
+        T_PAUSE => "pause",
+        T_CMD => "cmd",
+        T_SEND => "send",
+        T_STATUS => "status",
+        T_FADE => "fade",
+        _ => return None,
+    };
+
+    Some(name)
+}
 
 pub trait Token: Debug {
     fn to_bytecode(&self) -> Vec<u8>;
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()>;
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()>;
 }
 
 pub trait FromBytecode {
@@ -54,7 +138,7 @@ impl Token for NopToken {
     fn to_bytecode(&self) -> Vec<u8> {
         vec![T_NOP]
     }
-    fn invoke(&self, _: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, _: &mut Runtime) -> VmResult<()> {
         Ok(())
     }
 }
@@ -75,16 +159,9 @@ impl Token for ExitToken {
         vec![T_EXIT, self.register]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        let mut exit_code = 0;
-        if let Some(rg) = runtime.get_1byte_register(self.register) {
-            exit_code = rg.get();
-        } else if let Some(rg) = runtime.get_4byte_register(self.register) {
-            exit_code = rg.get() as u8;
-        } else if self.register == RCS {
-            exit_code = runtime.rcs.get() as u8;
-        }
-        runtime.exit(exit_code);
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let exit_code = runtime.read_register(self.register)?;
+        runtime.exit(exit_code as u8);
 
         Ok(())
     }
@@ -107,16 +184,8 @@ impl Token for SetToken {
         vec![T_SET, self.value, self.register]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        if let Some(rg) = &mut runtime.get_1byte_register(self.register) {
-            rg.set(self.value);
-        } else if let Some(rg) = &mut runtime.get_4byte_register(self.register) {
-            rg.set(self.value as u32);
-        } else if self.register == RCS {
-            runtime.rcs.set(self.value != 0);
-        }
-
-        Ok(())
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.write_register(self.register, self.value as u32)
     }
 }
 
@@ -129,6 +198,35 @@ impl FromBytecode for SetToken {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct SetwToken {
+    pub value: u32,
+    pub register: u8,
+}
+
+impl Token for SetwToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        let mut bytecode = vec![T_SETW];
+        bytecode.append(&mut self.value.to_be_bytes().to_vec());
+        bytecode.push(self.register);
+
+        bytecode
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.write_register(self.register, self.value)
+    }
+}
+
+impl FromBytecode for SetwToken {
+    fn from_bytecode(code: &[&u8]) -> Self {
+        Self {
+            value: u32::from_be_bytes([*code[1], *code[2], *code[3], *code[4]]),
+            register: *code[5],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CopyToken {
     pub register_1: u8,
@@ -140,29 +238,9 @@ impl Token for CopyToken {
         vec![T_COPY, self.register_1, self.register_2]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        let value;
-        if let Some(rg) = runtime.get_1byte_register(self.register_1) {
-            value = rg.get() as u32;
-        } else if let Some(rg) = runtime.get_4byte_register(self.register_1) {
-            value = rg.get();
-        } else if self.register_1 == RCS {
-            value = runtime.rcs.get() as u32;
-        } else {
-            panic!("unknown register {}", self.register_1);
-        }
-
-        if let Some(rg) = &mut runtime.get_1byte_register(self.register_2) {
-            rg.set(value as u8);
-        } else if let Some(rg) = &mut runtime.get_4byte_register(self.register_2) {
-            rg.set(value);
-        } else if self.register_2 == RCS {
-            runtime.rcs.set(value != 0);
-        } else {
-            panic!("unknown register {}", self.register_2);
-        }
-
-        Ok(())
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let value = runtime.read_register(self.register_1)?;
+        runtime.write_register(self.register_2, value)
     }
 }
 
@@ -183,7 +261,7 @@ impl Token for LoadToken {
         vec![T_LOAD]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         let pointer = runtime.rgp.get();
         if let Some(value) = runtime.memory.get(&pointer) {
             runtime.rgd.set(*value);
@@ -211,16 +289,8 @@ impl Token for ClearToken {
         vec![T_CLEAR, self.register]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        if let Some(rg) = &mut runtime.get_1byte_register(self.register) {
-            rg.set(0u8);
-        } else if let Some(rg) = &mut runtime.get_4byte_register(self.register) {
-            rg.set(0u32);
-        } else if self.register == RCS {
-            runtime.rcs.set(false);
-        }
-
-        Ok(())
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.write_register(self.register, 0)
     }
 }
 
@@ -238,10 +308,8 @@ impl Token for WriteToken {
         vec![T_WRITE]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.memory.insert(runtime.rgp.get(), runtime.rgd.get());
-
-        Ok(())
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.write_memory(runtime.rgp.get(), runtime.rgd.get())
     }
 }
 
@@ -264,7 +332,7 @@ impl Token for LabelToken {
         bytecode
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         runtime.create_label(self.value);
 
         Ok(())
@@ -287,7 +355,7 @@ impl Token for GotoToken {
         vec![T_GOTO]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         runtime.jump(runtime.rgl.get())
     }
 }
@@ -300,9 +368,12 @@ impl Token for DebugToken {
         vec![T_DEBUG]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        if let Some(location) = runtime.source_location(runtime.current_index()) {
+            println!("--- {} ---", location);
+        }
         println!("--- Registers --");
-        println!("rcs: {}", runtime.rcs.get());
+        println!("rcs: {}", runtime.snapshot().rcs);
         println!("rcr: {}", runtime.rcr.get());
         println!("rcg: {}", runtime.rcg.get());
         println!("rcb: {}", runtime.rcb.get());
@@ -311,8 +382,20 @@ impl Token for DebugToken {
         println!("rgi: {}", runtime.rgi.get());
         println!("rgo: {}", runtime.rgo.get());
         println!("rgl: {}", runtime.rgl.get());
+        println!("rgs: {}", runtime.rgs.get());
+        println!("rhh: {}", runtime.rhh.get());
+        println!("rhs: {}", runtime.rhs.get());
+        println!("rhv: {}", runtime.rhv.get());
         println!("\n--- Runtime ---");
-        println!("Labels: {:?}", runtime.labels);
+        let labels: HashMap<String, usize> = runtime
+            .labels
+            .iter()
+            .map(|(id, index)| match runtime.symbols.get(id) {
+                Some(name) => (name.clone(), *index),
+                None => (id.to_string(), *index),
+            })
+            .collect();
+        println!("Labels: {:?}", labels);
         println!("Memory: {:?}", runtime.memory);
         println!();
 
@@ -330,17 +413,8 @@ impl Token for PrintToken {
         vec![T_PRINT, self.register]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        let value = if let Some(rg) = runtime.get_1byte_register(self.register) {
-            rg.get() as u32
-        } else if let Some(rg) = runtime.get_4byte_register(self.register) {
-            rg.get()
-        } else if self.register == RCS {
-            runtime.rcs.get() as u32
-        } else {
-            0
-        };
-
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let value = runtime.read_register(self.register)?;
         println!("{}", value);
 
         Ok(())
@@ -361,8 +435,15 @@ impl Token for AddToken {
         vec![T_ADD]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() + runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let rgd = runtime.rgd.get();
+        let rgi = runtime.rgi.get();
+        let value = runtime.overflowing(
+            rgd.checked_add(rgi),
+            rgd.wrapping_add(rgi),
+            rgd.saturating_add(rgi),
+        )?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -375,8 +456,15 @@ impl Token for SubToken {
         vec![T_SUB]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() - runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let rgd = runtime.rgd.get();
+        let rgi = runtime.rgi.get();
+        let value = runtime.overflowing(
+            rgd.checked_sub(rgi),
+            rgd.wrapping_sub(rgi),
+            rgd.saturating_sub(rgi),
+        )?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -390,8 +478,15 @@ impl Token for MulToken {
         vec![T_MUL]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() * runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let rgd = runtime.rgd.get();
+        let rgi = runtime.rgi.get();
+        let value = runtime.overflowing(
+            rgd.checked_mul(rgi),
+            rgd.wrapping_mul(rgi),
+            rgd.saturating_mul(rgi),
+        )?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -405,8 +500,13 @@ impl Token for DivToken {
         vec![T_DIV]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() / runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let value = runtime
+            .rgd
+            .get()
+            .checked_div(runtime.rgi.get())
+            .ok_or_else(|| runtime.fault(FaultKind::DivisionByZero))?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -420,8 +520,13 @@ impl Token for ModToken {
         vec![T_MOD]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() % runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let value = runtime
+            .rgd
+            .get()
+            .checked_rem(runtime.rgi.get())
+            .ok_or_else(|| runtime.fault(FaultKind::DivisionByZero))?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -435,8 +540,17 @@ impl Token for LshToken {
         vec![T_LSH]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() << runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let rgd = runtime.rgd.get();
+        let rgi = runtime.rgi.get();
+        let shifted = rgd.checked_shl(rgi).unwrap_or(0);
+        let checked = if rgd == 0 || (rgi < 32 && shifted >> rgi == rgd) {
+            Some(shifted)
+        } else {
+            None
+        };
+        let value = runtime.overflowing(checked, shifted, u32::MAX)?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -450,8 +564,13 @@ impl Token for RshToken {
         vec![T_RSH]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(runtime.rgd.get() >> runtime.rgi.get());
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let value = runtime
+            .rgd
+            .get()
+            .checked_shr(runtime.rgi.get())
+            .unwrap_or(0);
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -465,7 +584,7 @@ impl Token for AndToken {
         vec![T_AND]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         runtime.rgo.set(runtime.rgd.get() & runtime.rgi.get());
 
         Ok(())
@@ -480,7 +599,7 @@ impl Token for OrToken {
         vec![T_OR]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         runtime.rgo.set(runtime.rgd.get() | runtime.rgi.get());
 
         Ok(())
@@ -495,7 +614,7 @@ impl Token for NotToken {
         vec![T_NOT]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         runtime.rgo.set(!runtime.rgd.get());
 
         Ok(())
@@ -510,7 +629,7 @@ impl Token for XorToken {
         vec![T_XOR]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         runtime.rgo.set(runtime.rgd.get().bitxor(runtime.rgi.get()));
 
         Ok(())
@@ -525,12 +644,15 @@ impl Token for PowToken {
         vec![T_POW]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        runtime.rgo.set(
-            (runtime.rgd.get() as f32)
-                .powi(runtime.rgi.get() as i32)
-                .round() as u32,
-        );
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let rgd = runtime.rgd.get();
+        let rgi = runtime.rgi.get();
+        let value = runtime.overflowing(
+            rgd.checked_pow(rgi),
+            rgd.wrapping_pow(rgi),
+            rgd.saturating_pow(rgi),
+        )?;
+        runtime.rgo.set(value);
 
         Ok(())
     }
@@ -544,7 +666,10 @@ impl Token for NrtToken {
         vec![T_NRT]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        if runtime.rgi.get() == 0 {
+            return Err(runtime.fault(FaultKind::InvalidRootDegree));
+        }
         runtime
             .rgo
             .set(runtime.rgd.get().nth_root(runtime.rgi.get()));
@@ -561,7 +686,7 @@ impl Token for JgToken {
         vec![T_JG]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         if runtime.rgd.get() > runtime.rgi.get() {
             runtime.jump(runtime.rgl.get())?;
         }
@@ -578,7 +703,7 @@ impl Token for JlToken {
         vec![T_JL]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         if runtime.rgd.get() < runtime.rgi.get() {
             runtime.jump(runtime.rgl.get())?;
         }
@@ -595,7 +720,7 @@ impl Token for JeToken {
         vec![T_JE]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         if runtime.rgd.get() == runtime.rgi.get() {
             runtime.jump(runtime.rgl.get())?;
         }
@@ -604,6 +729,22 @@ impl Token for JeToken {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct OverflowToken {
+    pub mode: OverflowMode,
+}
+
+impl Token for OverflowToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_OVERFLOW, self.mode as u8]
+    }
+
+    /// The overflow mode is applied when the bytecode is parsed
+    fn invoke(&self, _: &mut Runtime) -> VmResult<()> {
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PauseToken;
 
@@ -612,10 +753,8 @@ impl Token for PauseToken {
         vec![T_PAUSE]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
-        sleep(Duration::from_millis(runtime.rgd.get() as u64));
-
-        Ok(())
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.pause(Duration::from_millis(runtime.rgd.get() as u64))
     }
 }
 
@@ -627,8 +766,19 @@ impl Token for CmdToken {
         vec![T_CMD]
     }
 
-    fn invoke(&self, _: &mut Runtime) -> io::Result<()> {
-        unimplemented!();
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let program = runtime.rgd.get();
+        let speed = runtime.rgi.get();
+        let command = u8::try_from(program)
+            .ok()
+            .and_then(ProgramStripCommand::from_code)
+            .ok_or_else(|| runtime.fault(FaultKind::UnknownProgram(program)))?;
+        let speed = u8::try_from(speed)
+            .ok()
+            .filter(|speed| PROGRAM_SPEEDS.contains(speed))
+            .ok_or_else(|| runtime.fault(FaultKind::InvalidSpeed(speed)))?;
+
+        runtime.with_strip(|strip| strip.controller.send_command(command, speed))
     }
 }
 
@@ -640,14 +790,231 @@ impl Token for SendToken {
         vec![T_SEND]
     }
 
-    fn invoke(&self, runtime: &mut Runtime) -> io::Result<()> {
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
         let r = runtime.rcr.get();
         let g = runtime.rcg.get();
         let b = runtime.rcb.get();
 
-        runtime
-            .strip_controller
-            .borrow_mut()
-            .send_rgb_color(r, g, b)
+        runtime.with_strip(|strip| strip.controller.send_rgb_color(r, g, b))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusToken;
+
+impl Token for StatusToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_STATUS]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.load_strip_status()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PixelToken;
+
+impl Token for PixelToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_PIXEL]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let index = runtime.rgp.get();
+        let color = (runtime.rcr.get(), runtime.rcg.get(), runtime.rcb.get());
+
+        let pixel_set = runtime.with_frame(|pixels| match pixels.get_mut(index as usize) {
+            Some(pixel) => {
+                *pixel = color;
+                true
+            }
+            None => false,
+        })?;
+        if !pixel_set {
+            return Err(runtime.fault(FaultKind::PixelOutOfRange(index)));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FillToken;
+
+impl Token for FillToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_FILL]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let start = runtime.rgp.get() as usize;
+        let count = runtime.rgi.get() as usize;
+        let color = (runtime.rcr.get(), runtime.rcg.get(), runtime.rcb.get());
+
+        let filled = runtime.with_frame(|pixels| {
+            match pixels.get_mut(start..start.saturating_add(count)) {
+                Some(range) => {
+                    range.iter_mut().for_each(|pixel| *pixel = color);
+                    true
+                }
+                None => false,
+            }
+        })?;
+        if !filled {
+            let last = start.saturating_add(count).saturating_sub(1);
+            return Err(runtime.fault(FaultKind::PixelOutOfRange(last as u32)));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShiftToken;
+
+impl Token for ShiftToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_SHIFT]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let amount = runtime.rgd.get() as i32;
+
+        runtime.with_frame(|pixels| {
+            let length = pixels.len();
+            let count = (amount.unsigned_abs() as usize).min(length);
+            if amount >= 0 {
+                pixels.rotate_right(count);
+                pixels[..count].iter_mut().for_each(|p| *p = (0, 0, 0));
+            } else {
+                pixels.rotate_left(count);
+                pixels[length - count..]
+                    .iter_mut()
+                    .for_each(|p| *p = (0, 0, 0));
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RotateToken;
+
+impl Token for RotateToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_ROTATE]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let amount = runtime.rgd.get() as i32 as i64;
+
+        runtime.with_frame(|pixels| {
+            if !pixels.is_empty() {
+                let count = amount.rem_euclid(pixels.len() as i64) as usize;
+                pixels.rotate_right(count);
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShowToken;
+
+impl Token for ShowToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_SHOW]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.with_strip(|strip| strip.controller.send_frame())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FadeToken {
+    pub easing: Easing,
+    pub background: bool,
+}
+
+impl Token for FadeToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        let background = if self.background { FADE_BACKGROUND } else { 0 };
+        vec![T_FADE, self.easing as u8 | background]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        runtime.fade(self.easing, self.background)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HsvToken;
+
+impl Token for HsvToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_HSV]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let (r, g, b) = hsv_to_rgb((runtime.rhh.get(), runtime.rhs.get(), runtime.rhv.get()));
+        runtime.rcr.set(r);
+        runtime.rcg.set(g);
+        runtime.rcb.set(b);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HslToken;
+
+impl Token for HslToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_HSL]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let (r, g, b) = hsl_to_rgb((runtime.rhh.get(), runtime.rhs.get(), runtime.rhv.get()));
+        runtime.rcr.set(r);
+        runtime.rcg.set(g);
+        runtime.rcb.set(b);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RgbHsvToken;
+
+impl Token for RgbHsvToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_RGBHSV]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let (h, s, v) = rgb_to_hsv((runtime.rcr.get(), runtime.rcg.get(), runtime.rcb.get()));
+        runtime.rhh.set(h);
+        runtime.rhs.set(s);
+        runtime.rhv.set(v);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RgbHslToken;
+
+impl Token for RgbHslToken {
+    fn to_bytecode(&self) -> Vec<u8> {
+        vec![T_RGBHSL]
+    }
+
+    fn invoke(&self, runtime: &mut Runtime) -> VmResult<()> {
+        let (h, s, v) = rgb_to_hsl((runtime.rcr.get(), runtime.rcg.get(), runtime.rcb.get()));
+        runtime.rhh.set(h);
+        runtime.rhs.set(s);
+        runtime.rhv.set(v);
+
+        Ok(())
     }
 }
@@ -0,0 +1,507 @@
+use crate::backends::StripBackend;
+use crate::clock::{real_clock, Clock};
+use crate::ledstrip_controller::Pixel;
+use std::cell::RefCell;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// The first line of every trace file
+pub const TRACE_HEADER: &str = "# ledstrip-vm trace 1";
+
+/// Something that happened while a program was executed
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// A label of the program with the index of its instruction and its name
+    Label {
+        id: u32,
+        index: usize,
+        name: Option<String>,
+    },
+    /// An instruction is about to be executed
+    Instruction {
+        index: usize,
+        opcode: u8,
+    },
+    /// The value of a register changed
+    Register {
+        code: u8,
+        value: u32,
+    },
+    /// A value was written into memory
+    Memory {
+        address: u32,
+        value: u32,
+    },
+    /// A message was sent to the strip with the number
+    Message {
+        strip: usize,
+        bytes: Vec<u8>,
+    },
+    /// A frame was sent to the addressable strip with the number
+    Frame {
+        strip: usize,
+        pixels: Vec<Pixel>,
+    },
+    Exit(u8),
+    Fault(String),
+}
+
+/// An event with the time since the start of the trace in microseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub time: u64,
+    pub event: TraceEvent,
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceEvent::Label { id, index, name } => {
+                write!(f, "L {:X} {} {}", id, index, name.as_deref().unwrap_or("-"))
+            }
+            TraceEvent::Instruction { index, opcode } => write!(f, "I {} {:02X}", index, opcode),
+            TraceEvent::Register { code, value } => write!(f, "R {:02X} {:X}", code, value),
+            TraceEvent::Memory { address, value } => write!(f, "M {:X} {:X}", address, value),
+            TraceEvent::Message { strip, bytes } => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "S {} {}", strip, bytes.join(""))
+            }
+            TraceEvent::Frame { strip, pixels } => {
+                let pixels: Vec<String> = pixels
+                    .iter()
+                    .map(|(r, g, b)| format!("{:02x}{:02x}{:02x}", r, g, b))
+                    .collect();
+                write!(f, "P {} {}", strip, pixels.join(" "))
+            }
+            TraceEvent::Exit(code) => write!(f, "X {}", code),
+            TraceEvent::Fault(message) => write!(f, "E {}", message),
+        }
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.time, self.event)
+    }
+}
+
+impl FromStr for TraceEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid trace line '{}'", s);
+        let mut parts = s.splitn(3, ' ');
+        let time = parts
+            .next()
+            .and_then(|time| time.parse().ok())
+            .ok_or_else(invalid)?;
+        let kind = parts.next().ok_or_else(invalid)?;
+        let rest = parts.next().unwrap_or("");
+        let fields: Vec<&str> = rest.split(' ').collect();
+        let hex = |i: usize| {
+            fields
+                .get(i)
+                .and_then(|field| u32::from_str_radix(field, 16).ok())
+                .ok_or_else(invalid)
+        };
+        let number = |i: usize| {
+            fields
+                .get(i)
+                .and_then(|field| field.parse::<usize>().ok())
+                .ok_or_else(invalid)
+        };
+
+        let event = match kind {
+            "L" => TraceEvent::Label {
+                id: hex(0)?,
+                index: number(1)?,
+                name: fields
+                    .get(2)
+                    .filter(|name| **name != "-")
+                    .map(|name| name.to_string()),
+            },
+            "I" => TraceEvent::Instruction {
+                index: number(0)?,
+                opcode: hex(1)? as u8,
+            },
+            "R" => TraceEvent::Register {
+                code: hex(0)? as u8,
+                value: hex(1)?,
+            },
+            "M" => TraceEvent::Memory {
+                address: hex(0)?,
+                value: hex(1)?,
+            },
+            "S" => TraceEvent::Message {
+                strip: number(0)?,
+                bytes: decode_hex(fields.get(1).unwrap_or(&"")).ok_or_else(invalid)?,
+            },
+            "P" => TraceEvent::Frame {
+                strip: number(0)?,
+                pixels: fields[1..]
+                    .iter()
+                    .filter(|pixel| !pixel.is_empty())
+                    .map(|pixel| match decode_hex(pixel)?.as_slice() {
+                        [r, g, b] => Some((*r, *g, *b)),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<Pixel>>>()
+                    .ok_or_else(invalid)?,
+            },
+            "X" => TraceEvent::Exit(number(0)? as u8),
+            "E" => TraceEvent::Fault(rest.to_string()),
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self { time, event })
+    }
+}
+
+/// Decodes a string of hex digit pairs
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Writes the events of a program into a file with one event per line
+#[derive(Debug)]
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    clock: Rc<dyn Clock>,
+}
+
+impl TraceWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", TRACE_HEADER)?;
+
+        Ok(Self {
+            writer,
+            clock: real_clock(),
+        })
+    }
+
+    /// Sets the clock for the times of the events
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Writes the event with the time of the clock
+    pub fn record(&mut self, event: TraceEvent) -> io::Result<()> {
+        let entry = TraceEntry {
+            time: self.clock.now().as_micros() as u64,
+            event,
+        };
+        writeln!(self.writer, "{}", entry)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads all events of a trace file
+pub fn read_trace(path: &str) -> io::Result<Vec<TraceEntry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Records the messages and frames of a strip before passing them on
+#[derive(Debug)]
+pub struct TraceBackend {
+    inner: Box<dyn StripBackend>,
+    strip: usize,
+    trace: Rc<RefCell<TraceWriter>>,
+}
+
+impl TraceBackend {
+    pub fn new(
+        inner: Box<dyn StripBackend>,
+        strip: usize,
+        trace: Rc<RefCell<TraceWriter>>,
+    ) -> Self {
+        Self {
+            inner,
+            strip,
+            trace,
+        }
+    }
+}
+
+impl StripBackend for TraceBackend {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.trace.borrow_mut().record(TraceEvent::Message {
+            strip: self.strip,
+            bytes: message.to_vec(),
+        })?;
+        self.inner.send(message)
+    }
+
+    fn send_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
+        self.trace.borrow_mut().record(TraceEvent::Frame {
+            strip: self.strip,
+            pixels: pixels.to_vec(),
+        })?;
+        self.inner.send_frame(pixels)
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.inner.receive(buffer)
+    }
+
+    fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.inner.set_clock(clock)
+    }
+
+    fn take_disconnected(&mut self) -> bool {
+        self.inner.take_disconnected()
+    }
+}
+
+/// The first event that differs between two traces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The number of the event in both traces
+    pub event: usize,
+    /// The index of the last instruction both traces executed before
+    pub instruction: Option<usize>,
+    pub left: Option<TraceEntry>,
+    pub right: Option<TraceEntry>,
+}
+
+/// Compares the events of two traces without their times.
+/// Returns None if both traces contain the same events
+pub fn diff_traces(left: &[TraceEntry], right: &[TraceEntry]) -> Option<Divergence> {
+    let mut instruction = None;
+    for event in 0..left.len().max(right.len()) {
+        let (l, r) = (left.get(event), right.get(event));
+        if l.map(|entry| &entry.event) != r.map(|entry| &entry.event) {
+            return Some(Divergence {
+                event,
+                instruction,
+                left: l.cloned(),
+                right: r.cloned(),
+            });
+        }
+        if let Some(TraceEvent::Instruction { index, .. }) = l.map(|entry| &entry.event) {
+            instruction = Some(*index);
+        }
+    }
+
+    None
+}
+
+/// How often the code after a label was executed
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelCount {
+    pub id: u32,
+    pub name: Option<String>,
+    /// The number of times the label was reached
+    pub reached: u64,
+    /// The number of instructions executed from the label up to the next one
+    pub instructions: u64,
+}
+
+/// Counts the executions of every label of the trace in the order of the code.
+/// Instructions before the first label aren't counted
+pub fn label_counts(entries: &[TraceEntry]) -> Vec<LabelCount> {
+    let mut labels: Vec<(usize, LabelCount)> = entries
+        .iter()
+        .filter_map(|entry| match &entry.event {
+            TraceEvent::Label { id, index, name } => Some((
+                *index,
+                LabelCount {
+                    id: *id,
+                    name: name.clone(),
+                    reached: 0,
+                    instructions: 0,
+                },
+            )),
+            _ => None,
+        })
+        .collect();
+    labels.sort_by_key(|(index, _)| *index);
+
+    let mut previous = None;
+    for entry in entries {
+        if let TraceEvent::Instruction { index, .. } = entry.event {
+            let block = labels.iter().rposition(|(start, _)| *start <= index);
+            if let Some(block) = block {
+                let (start, count) = &mut labels[block];
+                count.instructions += 1;
+                // jumps continue after the label instruction
+                if *start == index || (*start + 1 == index && previous != Some(*start)) {
+                    count.reached += 1;
+                }
+            }
+            previous = Some(index);
+        }
+    }
+
+    labels.into_iter().map(|(_, count)| count).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: u64, event: TraceEvent) -> TraceEntry {
+        TraceEntry { time, event }
+    }
+
+    fn instructions(indices: &[usize]) -> Vec<TraceEntry> {
+        indices
+            .iter()
+            .map(|index| {
+                entry(
+                    0,
+                    TraceEvent::Instruction {
+                        index: *index,
+                        opcode: 0x10,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lines_survive_a_round_trip() {
+        let events = vec![
+            TraceEvent::Label {
+                id: 0x1F,
+                index: 3,
+                name: Some("loop".to_string()),
+            },
+            TraceEvent::Label {
+                id: 0x100,
+                index: 7,
+                name: None,
+            },
+            TraceEvent::Instruction {
+                index: 12,
+                opcode: 0xF4,
+            },
+            TraceEvent::Register {
+                code: 0x05,
+                value: 0xDEAD_BEEF,
+            },
+            TraceEvent::Memory {
+                address: 0x10,
+                value: 0,
+            },
+            TraceEvent::Message {
+                strip: 1,
+                bytes: vec![0x71, 0x23, 0x0f, 0xa3],
+            },
+            TraceEvent::Frame {
+                strip: 0,
+                pixels: vec![(0xff, 0x00, 0x10), (0, 0, 0)],
+            },
+            TraceEvent::Frame {
+                strip: 0,
+                pixels: Vec::new(),
+            },
+            TraceEvent::Exit(3),
+            TraceEvent::Fault("division by zero".to_string()),
+        ];
+        for (time, event) in events.into_iter().enumerate() {
+            let entry = entry(time as u64 * 1500, event);
+            let line = entry.to_string();
+            let parsed: TraceEntry = line.parse().unwrap();
+            assert_eq!(parsed, entry);
+            assert_eq!(parsed.to_string(), line);
+        }
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        for line in ["", "10", "10 Q 1", "10 I x 10", "10 S 0 abc", "10 P 0 ffff"].iter() {
+            assert!(line.parse::<TraceEntry>().is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn diff_finds_the_first_divergence() {
+        let mut left = instructions(&[0, 1]);
+        left.push(entry(5, TraceEvent::Register { code: 5, value: 1 }));
+        left.append(&mut instructions(&[2]));
+        let mut right = left.clone();
+        assert_eq!(diff_traces(&left, &right), None);
+
+        // times are ignored
+        right[2].time = 7;
+        assert_eq!(diff_traces(&left, &right), None);
+
+        right[2].event = TraceEvent::Register { code: 5, value: 2 };
+        let divergence = diff_traces(&left, &right).unwrap();
+        assert_eq!(divergence.event, 2);
+        assert_eq!(divergence.instruction, Some(1));
+        assert_eq!(divergence.left, Some(left[2].clone()));
+        assert_eq!(divergence.right, Some(right[2].clone()));
+
+        let divergence = diff_traces(&left, &left[..3]).unwrap();
+        assert_eq!(divergence.event, 3);
+        assert_eq!(divergence.right, None);
+    }
+
+    #[test]
+    fn labels_are_counted_across_jumps() {
+        // 1: label 0x01, 3: a jump back to label 0x01, 4: label 0x02
+        let mut entries = vec![
+            entry(
+                0,
+                TraceEvent::Label {
+                    id: 1,
+                    index: 1,
+                    name: Some("loop".to_string()),
+                },
+            ),
+            entry(
+                0,
+                TraceEvent::Label {
+                    id: 2,
+                    index: 4,
+                    name: None,
+                },
+            ),
+        ];
+        entries.append(&mut instructions(&[0, 1, 2, 3, 2, 3, 2, 3, 4, 5]));
+
+        assert_eq!(
+            label_counts(&entries),
+            vec![
+                LabelCount {
+                    id: 1,
+                    name: Some("loop".to_string()),
+                    reached: 3,
+                    instructions: 7,
+                },
+                LabelCount {
+                    id: 2,
+                    name: None,
+                    reached: 1,
+                    instructions: 2,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use ledstrip_vm::assembler::assemble;
+use ledstrip_vm::backends::{backend_from_spec, TcpOptions};
+use ledstrip_vm::fake_controller::FakeController;
+use ledstrip_vm::ledstrip_controller::{ProgramStripCommand, StripMessage, StripStatus};
+use ledstrip_vm::runtime::Runtime;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Assembles the program and runs it against the fake controller
+fn run(controller: &FakeController, source: &str) -> u8 {
+    let spec = format!("tcp:{}", controller.address());
+    let backend = backend_from_spec(&spec, &TcpOptions::default()).unwrap();
+    let mut runtime = Runtime::new(backend);
+    runtime
+        .parse_bytecode(&assemble(source, false).unwrap())
+        .unwrap();
+
+    runtime.run().unwrap()
+}
+
+#[test]
+fn programs_send_the_expected_commands() {
+    let controller = FakeController::bind("127.0.0.1:0").unwrap();
+    let code = run(
+        &controller,
+        "\
+set 0xFF rcs
+set 0xFF rcr
+set 0x10 rcg
+send
+send
+set 0x25 rgd
+set 0x05 rgi
+cmd
+",
+    );
+
+    assert_eq!(code, 0);
+    assert!(controller.wait_for(5, TIMEOUT));
+    assert_eq!(
+        controller.messages(),
+        vec![
+            StripMessage::State(true),
+            StripMessage::Color(0xff, 0x10, 0x00),
+            StripMessage::Color(0xff, 0x10, 0x00),
+            StripMessage::Program(ProgramStripCommand::SevenCrossFade, 0x05),
+            StripMessage::State(false),
+        ]
+    );
+    assert!(controller.errors().is_empty());
+}
+
+#[test]
+fn status_queries_load_the_reported_color() {
+    let controller = FakeController::bind("127.0.0.1:0").unwrap();
+    controller.set_status(StripStatus {
+        on: true,
+        r: 0x12,
+        g: 0x34,
+        b: 0x56,
+    });
+    run(
+        &controller,
+        "\
+status
+send
+set 0x00 rcb
+send
+",
+    );
+
+    assert!(controller.wait_for(3, TIMEOUT));
+    assert_eq!(controller.queries(), 1);
+    assert_eq!(
+        controller.messages(),
+        vec![
+            StripMessage::Color(0x12, 0x34, 0x56),
+            StripMessage::Color(0x12, 0x34, 0x00),
+            StripMessage::State(false),
+        ]
+    );
+}
+
+#[test]
+fn messages_with_a_wrong_checksum_are_rejected() {
+    let controller = FakeController::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(controller.address()).unwrap();
+    let invalid = [0x31, 0xff, 0x00, 0x00, 0xf0, 0x0f, 0x2e];
+    stream.write_all(&invalid).unwrap();
+    stream.write_all(&[0x71, 0x23, 0x0f, 0xa3]).unwrap();
+
+    assert!(controller.wait_for(1, TIMEOUT));
+    assert_eq!(controller.messages(), vec![StripMessage::State(true)]);
+    let errors = controller.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].bytes, invalid);
+}
@@ -0,0 +1,44 @@
+use std::env;
+use std::fs::{remove_file, write};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Writes the source into a temporary file and assembles it with lsambler.
+/// Returns the output of lsambler and the path of the output file
+fn assemble(name: &str, source: &str) -> (Output, PathBuf) {
+    let directory = env::temp_dir();
+    let input = directory.join(format!("lsambler-{}-{}.sasm", name, std::process::id()));
+    let output_file = directory.join(format!("lsambler-{}-{}.lsb", name, std::process::id()));
+    write(&input, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lsambler"))
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output_file)
+        .output()
+        .unwrap();
+    remove_file(&input).unwrap();
+
+    (output, output_file)
+}
+
+#[test]
+fn valid_programs_are_written() {
+    let (output, output_file) = assemble("valid", "set 0x01 rcr\nexit rcr\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output_file.exists());
+    remove_file(output_file).unwrap();
+}
+
+#[test]
+fn errors_are_reported_with_their_position() {
+    let (output, output_file) = assemble("errors", "set 0x01 rcr\nst 0x01 rcr\nset 0x01 rgx\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output_file.exists());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(".sasm:2:1\n"), "{}", stderr);
+    assert!(stderr.contains(".sasm:3:10\n"), "{}", stderr);
+    assert!(stderr.contains("did you mean set?"), "{}", stderr);
+    assert!(stderr.contains("due to 2 error(s)"), "{}", stderr);
+}
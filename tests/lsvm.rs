@@ -0,0 +1,35 @@
+use ledstrip_vm::assembler::assemble;
+use std::env;
+use std::fs::{remove_file, write};
+use std::process::{Command, Output};
+
+/// Assembles the source into a temporary file and runs it with lsvm
+fn run(name: &str, source: &str) -> Output {
+    let path = env::temp_dir().join(format!("lsvm-{}-{}.lsb", name, std::process::id()));
+    write(&path, assemble(source, false).unwrap()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lsvm"))
+        .arg("-i")
+        .arg(&path)
+        .args(["--backend", "null"])
+        .output()
+        .unwrap();
+    remove_file(&path).unwrap();
+
+    output
+}
+
+#[test]
+fn programs_that_exit_succeed() {
+    let output = run("exit", "set 0x00 rcr\nexit rcr\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn faults_exit_with_status_2() {
+    let output = run("fault", "set 0x05 rgd\nclear rgi\ndiv\n");
+    assert_eq!(output.status.code(), Some(2));
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("division by zero"), "{}", stderr);
+    assert!(stderr.contains("rgd"), "{}", stderr);
+}
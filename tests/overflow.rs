@@ -0,0 +1,75 @@
+use ledstrip_vm::assembler::assemble;
+use ledstrip_vm::backends::NullBackend;
+use ledstrip_vm::errors::{FaultKind, ParseError};
+use ledstrip_vm::registers::RCR;
+use ledstrip_vm::runtime::{OverflowMode, Runtime};
+use ledstrip_vm::tokens::{T_ADD, T_OVERFLOW};
+
+/// Writes 0xFF + 0x2D into the 1 byte register rcr
+const OVERFLOW: &str = "\
+set 0xFF rgd
+set 0x2D rgi
+add
+copy rgo rcr
+";
+
+fn parse(source: &str, legacy: bool) -> Runtime {
+    let mut runtime = Runtime::new(Box::new(NullBackend));
+    runtime.set_allow_legacy(legacy);
+    runtime
+        .parse_bytecode(&assemble(source, legacy).unwrap())
+        .unwrap();
+
+    runtime
+}
+
+#[test]
+fn programs_without_a_directive_wrap() {
+    for legacy in [false, true].iter() {
+        let mut runtime = parse(OVERFLOW, *legacy);
+        assert_eq!(runtime.run().unwrap(), 0);
+        assert_eq!(runtime.read_register(RCR).unwrap(), 0x2C);
+    }
+}
+
+#[test]
+fn the_directive_selects_the_mode() {
+    let mut runtime = parse(&format!(".overflow saturate\n{}", OVERFLOW), false);
+    runtime.run().unwrap();
+    assert_eq!(runtime.read_register(RCR).unwrap(), 0xFF);
+
+    let mut runtime = parse(&format!(".overflow trap\n{}", OVERFLOW), false);
+    let fault = runtime.run().unwrap_err();
+    assert!(matches!(fault.kind, FaultKind::ArithmeticOverflow));
+}
+
+#[test]
+fn the_option_overrides_the_directive() {
+    let mut runtime = parse(&format!(".overflow trap\n{}", OVERFLOW), false);
+    runtime.set_overflow_mode(OverflowMode::Wrap);
+    runtime.run().unwrap();
+    assert_eq!(runtime.read_register(RCR).unwrap(), 0x2C);
+}
+
+#[test]
+fn the_mode_can_only_be_set_once() {
+    let source = format!(".overflow trap\n{}.overflow saturate\n", OVERFLOW);
+    for legacy in [false, true].iter() {
+        let diagnostics = assemble(&source, *legacy).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 6);
+    }
+
+    let mut runtime = Runtime::new(Box::new(NullBackend));
+    runtime.set_allow_legacy(true);
+    let error = runtime
+        .parse_bytecode(&[T_OVERFLOW, 0x03, T_ADD, T_OVERFLOW, 0x02])
+        .unwrap_err();
+    assert_eq!(
+        error,
+        ParseError::DuplicateOverflowMode {
+            offset: 3,
+            opcode: T_OVERFLOW
+        }
+    );
+}
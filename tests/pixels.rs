@@ -0,0 +1,128 @@
+use ledstrip_vm::assembler::assemble;
+use ledstrip_vm::backends::{PixelBackend, StripBackend};
+use ledstrip_vm::errors::FaultKind;
+use ledstrip_vm::runtime::Runtime;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+const RED: [u8; 3] = [0xFF, 0x00, 0x00];
+const GREEN: [u8; 3] = [0x00, 0xFF, 0x00];
+const OFF: [u8; 3] = [0x00, 0x00, 0x00];
+
+/// A writer that keeps the written bytes readable after it was moved into a backend
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program on a strip with the given number of pixels
+/// and returns the result and the written frames
+fn run(source: &str, pixels: usize) -> (Result<u8, FaultKind>, Vec<u8>) {
+    let buffer = SharedBuffer::default();
+    let mut runtime = Runtime::new(Box::new(PixelBackend::new(buffer.clone())));
+    runtime.set_pixel_count(pixels);
+    runtime
+        .parse_bytecode(&assemble(source, false).unwrap())
+        .unwrap();
+    let result = runtime.run().map_err(|fault| fault.kind);
+    let frames = buffer.0.borrow().clone();
+
+    (result, frames)
+}
+
+/// Builds the expected frame with the count header
+fn frame(pixels: &[[u8; 3]]) -> Vec<u8> {
+    let mut frame = (pixels.len() as u16).to_be_bytes().to_vec();
+    for pixel in pixels {
+        frame.extend_from_slice(pixel);
+    }
+
+    frame
+}
+
+#[test]
+fn frame_buffer_instructions() {
+    let source = "\
+set 0xFF rcr
+set 0x00 rgp
+pixel
+set 0x03 rgp
+set 0x02 rgi
+clear rcr
+set 0xFF rcg
+fill
+show
+set 0x01 rgd
+shift
+show
+setw 0xFFFFFFFF rgd
+rotate
+show
+set 0x07 rgd
+rotate
+show
+setw 0xFFFFFFFE rgd
+shift
+show
+set 0x09 rgd
+shift
+show
+";
+    let (result, frames) = run(source, 5);
+    assert_eq!(result.unwrap(), 0);
+
+    let expected: Vec<u8> = [
+        [RED, OFF, OFF, GREEN, GREEN],
+        [OFF, RED, OFF, OFF, GREEN],
+        [RED, OFF, OFF, GREEN, OFF],
+        [GREEN, OFF, RED, OFF, OFF],
+        [RED, OFF, OFF, OFF, OFF],
+        [OFF, OFF, OFF, OFF, OFF],
+    ]
+    .iter()
+    .flat_map(|pixels| frame(pixels))
+    .collect();
+    assert_eq!(frames, expected);
+}
+
+#[test]
+fn pixels_outside_of_the_frame_buffer_fault() {
+    let (result, _) = run("set 0x05 rgp\npixel\n", 5);
+    assert!(matches!(result, Err(FaultKind::PixelOutOfRange(5))));
+
+    let (result, _) = run("set 0x03 rgp\nset 0x03 rgi\nfill\n", 5);
+    assert!(matches!(result, Err(FaultKind::PixelOutOfRange(5))));
+
+    let (result, _) = run("pixel\n", 0);
+    assert!(matches!(result, Err(FaultKind::PixelOutOfRange(0))));
+
+    let (result, frames) = run("set 0x03 rgd\nshift\nrotate\nshow\n", 0);
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(frames, [0x00, 0x00]);
+}
+
+#[test]
+fn frames_start_with_the_pixel_count() {
+    let buffer = SharedBuffer::default();
+    let mut backend = PixelBackend::new(buffer.clone());
+    backend
+        .send_frame(&vec![(0x12, 0x34, 0x56); 0x0102])
+        .unwrap();
+    let written = buffer.0.borrow().clone();
+    assert_eq!(written.len(), 2 + 3 * 0x0102);
+    assert_eq!(written[..5], [0x01, 0x02, 0x12, 0x34, 0x56]);
+
+    let error = backend.send_frame(&vec![(0, 0, 0); 0x1_0000]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(buffer.0.borrow().len(), written.len());
+}
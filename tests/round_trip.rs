@@ -0,0 +1,52 @@
+use ledstrip_vm::assembler::{assemble, assemble_with_source_map};
+use ledstrip_vm::disassembler::disassemble;
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+/// Returns the name and source of every example program
+fn examples() -> Vec<(String, String)> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut examples: Vec<(String, String)> = read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "sasm")
+        })
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            (name, read_to_string(&path).unwrap())
+        })
+        .collect();
+    examples.sort();
+    assert!(!examples.is_empty());
+
+    examples
+}
+
+#[test]
+fn examples_reassemble_into_the_same_bytecode() {
+    for (name, source) in examples() {
+        for legacy in [false, true].iter() {
+            let bytecode = assemble(&source, *legacy).unwrap();
+            let assembly = disassemble(&bytecode).unwrap();
+            let reassembled = assemble(&assembly, *legacy)
+                .unwrap_or_else(|e| panic!("{} doesn't reassemble: {:?}", name, e));
+            assert_eq!(reassembled, bytecode, "{} (legacy: {})", name, legacy);
+        }
+    }
+}
+
+#[test]
+fn source_maps_are_left_out() {
+    for (name, source) in examples() {
+        let bytecode = assemble_with_source_map(&source, &name).unwrap();
+        let assembly = disassemble(&bytecode).unwrap();
+        assert_eq!(
+            assemble(&assembly, false).unwrap(),
+            assemble(&source, false).unwrap(),
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use ledstrip_vm::assembler::assemble;
+use ledstrip_vm::backends::RecordingBackend;
+use ledstrip_vm::clock::VirtualClock;
+use ledstrip_vm::runtime::Runtime;
+use ledstrip_vm::trace::{read_trace, TraceEntry, TraceEvent, TraceWriter};
+use std::env::temp_dir;
+use std::fs::remove_file;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Pauses for a minute and fades to blue within a second
+const SUNRISE: &str = "\
+set 0xFF rcs
+set 0xFF rcr
+send
+setw 0xEA60 rgd
+pause
+set 0x00 rcr
+set 0xFF rcb
+setw 0x3E8 rgd
+fade
+";
+
+/// What a program did with a virtual clock
+#[derive(Debug, PartialEq)]
+struct Run {
+    frames: Vec<Vec<u8>>,
+    /// The messages of the trace with their times
+    messages: Vec<(u64, Vec<u8>)>,
+    /// The virtual time at the end
+    end: Duration,
+}
+
+fn run(name: &str, source: &str) -> Run {
+    let recording = RecordingBackend::new();
+    let mut runtime = Runtime::new(Box::new(recording.clone()));
+    runtime.set_clock(Rc::new(VirtualClock::new()));
+    runtime
+        .parse_bytecode(&assemble(source, false).unwrap())
+        .unwrap();
+    let path = temp_dir().join(format!("ledstrip-vm-{}-{}.trace", name, std::process::id()));
+    let path = path.to_str().unwrap();
+    runtime
+        .set_trace(TraceWriter::create(path).unwrap())
+        .unwrap();
+    assert_eq!(runtime.run().unwrap(), 0);
+
+    let messages = read_trace(path)
+        .unwrap()
+        .into_iter()
+        .filter_map(|TraceEntry { time, event }| match event {
+            TraceEvent::Message { bytes, .. } => Some((time, bytes)),
+            _ => None,
+        })
+        .collect();
+    remove_file(path).unwrap();
+
+    Run {
+        frames: recording.frames(),
+        messages,
+        end: runtime.now(),
+    }
+}
+
+#[test]
+fn pauses_and_fades_finish_immediately() {
+    let start = Instant::now();
+    let Run {
+        frames,
+        messages,
+        end,
+    } = run("sunrise", SUNRISE);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(end, Duration::from_secs(61));
+
+    // on, red, 50 frames of the fade and off
+    assert_eq!(frames.len(), 53);
+    assert_eq!(frames.len(), messages.len());
+    let times: Vec<u64> = messages.iter().map(|(time, _)| *time).collect();
+    assert_eq!(&times[..2], &[0, 0]);
+    for (frame, time) in times[2..52].iter().enumerate() {
+        assert_eq!(*time, 60_000_000 + 20_000 * (frame as u64 + 1));
+    }
+    assert_eq!(times[52], 61_000_000);
+}
+
+#[test]
+fn virtual_runs_are_deterministic() {
+    assert_eq!(run("first", SUNRISE), run("second", SUNRISE));
+}
+
+#[test]
+fn background_fades_continue_during_pauses() {
+    let source = "\
+set 0xFF rcs
+set 0xFF rcr
+send
+clear rcr
+set 0xFF rcb
+set 0x64 rgd
+fade background
+set 0xC8 rgd
+pause
+";
+    let Run { messages, end, .. } = run("background", source);
+    assert_eq!(end, Duration::from_millis(200));
+
+    let times: Vec<u64> = messages.iter().map(|(time, _)| *time).collect();
+    assert_eq!(
+        times,
+        [0, 0, 20_000, 40_000, 60_000, 80_000, 100_000, 200_000]
+    );
+    // the last frame of the fade is the end color
+    assert_eq!(messages[6].1, [0x31, 0x00, 0x00, 0xff, 0xf0, 0x0f, 0x2f]);
+}